@@ -0,0 +1,607 @@
+use iced::{
+    alignment::{Horizontal, Vertical},
+    executor, subscription,
+    theme::Theme,
+    widget::{column, container, row, text, vertical_slider, vertical_space},
+    window, Alignment, Color, {Application, Command, Element, Length, Subscription},
+};
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    board::{self, Board},
+    display::*,
+    map, mask, pattern, *,
+};
+
+pub struct CellSeq {
+    sequencer: Arc<Mutex<Sequencer>>,
+    scheduler: Scheduler,
+    snapshots: Snapshots,
+    inputs: Inputs,
+    output: Output,
+    // the ports found on the last scan, and the one being sent to
+    ports: Vec<Port>,
+    port: Port,
+    map: Board,
+    mask: Board,
+    rule_input: String,
+    seed_input: String,
+    session_path: String,
+    pattern_path: String,
+    pattern_offset: Cell,
+    drum_lane: usize,
+    learning: bool,
+    // the control waiting for a controller to be moved while learning
+    armed: Option<Control>,
+}
+
+pub struct Flags {
+    pub sequencer: Arc<Mutex<Sequencer>>,
+    pub scheduler: Scheduler,
+    pub snapshots: Snapshots,
+    pub inputs: Inputs,
+    pub output: Output,
+    pub port: Port,
+    pub session_path: Option<String>,
+    pub session: Option<Session>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    None,
+    MapMessage(board::Message),
+    MaskMessage(board::Message),
+    Snapshot(Snapshot),
+    MidiInput(MidiMessage),
+    ToggleTranspose,
+    ToggleFollowOctave,
+    ToggleLatch,
+    ToggleLearn,
+    ClearBindings,
+    AddCcOutput,
+    RemoveCcOutput(usize),
+    CcOutput(usize, CcOutput),
+    ToggleRecord,
+    OutputPort(Port),
+    RescanPorts,
+    ToggleClock,
+    ToggleSync,
+    RandomizeMap,
+    RandomizeMask,
+    RulePreset(RulePreset),
+    SeedInput(String),
+    SeedSubmit,
+    RuleInput(String),
+    RuleSubmit,
+    Topology(Topology),
+    GridWidth(usize),
+    GridHeight(usize),
+    CellSize(usize),
+    Reset,
+    ClearMap,
+    ClearMask,
+    Save,
+    SessionPath(String),
+    SaveSession,
+    OpenSession,
+    PatternPath(String),
+    PatternOffset(Cell),
+    ImportMap,
+    ImportMask,
+    ExportMap,
+    ExportMask,
+    TogglePlayback,
+    SpeedChanged(usize),
+    NewDivision(usize),
+    ToggleLoop,
+    LoopLength(usize),
+    ProbChanged(f32),
+    RandChanged(f32),
+    NewVMin(u8),
+    NewVMax(u8),
+    NewGateMin(f32),
+    NewGateMax(f32),
+    ChannelChange(u8),
+    Scale(Scale),
+    PitchMode(PitchMode),
+    NewOctave(u8),
+    OctaveRange(u8),
+    NewNote(Root),
+    DrumLane(usize),
+    DrumNote(u8),
+    Voices(u8),
+    Program(Option<u8>),
+    SelectLayer(usize),
+    AddLayer,
+    RemoveLayer,
+    Quit,
+}
+
+// learning works on the controls of the window
+impl Control {
+    // the control a message from the gui adjusts, if it can be learned
+    pub fn from_message(message: &Message) -> Option<Control> {
+        match message {
+            Message::SpeedChanged(_) => Some(Control::Bpm),
+            Message::NewDivision(_) => Some(Control::Divisor),
+            Message::LoopLength(_) => Some(Control::LoopLength),
+            Message::ProbChanged(_) => Some(Control::Probability),
+            Message::RandChanged(_) => Some(Control::Randomness),
+            Message::NewVMin(_) => Some(Control::VelocityMin),
+            Message::NewVMax(_) => Some(Control::VelocityMax),
+            Message::NewGateMin(_) => Some(Control::GateMin),
+            Message::NewGateMax(_) => Some(Control::GateMax),
+            Message::NewOctave(_) => Some(Control::OctaveCenter),
+            Message::OctaveRange(_) => Some(Control::OctaveRange),
+            Message::Voices(_) => Some(Control::Voices),
+            _ => None,
+        }
+    }
+
+    // spreads the 0-127 controller value over the range of the control
+    pub fn message(self, value: u8) -> Message {
+        let value = value.min(127);
+        let scale = |min: usize, max: usize| min + (max - min) * value as usize / 127;
+        let fraction = value as f32 / 127.0;
+
+        match self {
+            Control::Bpm => Message::SpeedChanged(scale(30, 300)),
+            Control::Divisor => Message::NewDivision(scale(1, 16)),
+            Control::LoopLength => Message::LoopLength(scale(1, 64)),
+            Control::Probability => Message::ProbChanged(fraction),
+            Control::Randomness => Message::RandChanged(fraction),
+            Control::VelocityMin => Message::NewVMin(value),
+            Control::VelocityMax => Message::NewVMax(value),
+            Control::GateMin => Message::NewGateMin(gate(fraction)),
+            Control::GateMax => Message::NewGateMax(gate(fraction)),
+            Control::OctaveCenter => Message::NewOctave(scale(0, 9) as u8),
+            Control::OctaveRange => Message::OctaveRange(scale(0, 4) as u8),
+            Control::Voices => Message::Voices(scale(1, 16) as u8),
+        }
+    }
+}
+
+// gate lengths are kept to whole multiples of the shortest gate
+fn gate(fraction: f32) -> f32 {
+    let gate = Gate::MIN + (Gate::MAX - Gate::MIN) * fraction;
+    (gate / Gate::MIN).round() * Gate::MIN
+}
+
+// no output is always there to pick, even when nothing else could be found
+fn scan_ports(output: &Output) -> Vec<Port> {
+    let mut ports = vec![Port::Off];
+    match output.backend().ports() {
+        Ok(found) => ports.extend(found),
+        Err(e) => eprintln!("couldn't list midi ports: {e}"),
+    }
+    ports
+}
+
+impl CellSeq {
+    fn sequencer(&self) -> MutexGuard<'_, Sequencer> {
+        self.sequencer.lock().unwrap()
+    }
+
+    fn control_message(&self) -> ControlMessage {
+        let sequencer = self.sequencer();
+        ControlMessage {
+            randomness: sequencer.map.randomness(),
+            rule: sequencer.map.rule(),
+            topology: sequencer.map.topology(),
+            grid: sequencer.grid,
+            pattern_offset: self.pattern_offset,
+            drum_lane: self.drum_lane,
+            learning: self.learning,
+            armed: self.armed,
+            info: sequencer.layer().info,
+            layer: sequencer.layer,
+            layers: sequencer.layers.len(),
+            song: sequencer.song,
+            keys: sequencer.keys,
+        }
+    }
+
+    fn draw(&mut self, snapshot: Snapshot) {
+        self.map
+            .set(snapshot.map, CellMap::default(), snapshot.grid);
+        self.mask.set(snapshot.mask, snapshot.hits, snapshot.grid);
+    }
+
+    // carries out a message without arming learn, for the ones coming from midi
+    // input so that a bound controller doesn't bind the next one it sends
+    fn apply(&mut self, message: Message) -> Command<Message> {
+        let mut sequencer = self.sequencer.lock().unwrap();
+
+        match message {
+            Message::None => {}
+            Message::Snapshot(snapshot) => {
+                drop(sequencer);
+                self.draw(snapshot);
+                return Command::none();
+            }
+            Message::MidiInput(MidiMessage::Cc {
+                controller,
+                value,
+                channel,
+            }) => {
+                if let Some(control) = self.armed.take() {
+                    sequencer.bind(Binding {
+                        channel,
+                        controller,
+                        control,
+                    });
+                } else if let Some(control) = sequencer.bound(channel, controller) {
+                    drop(sequencer);
+                    return self.apply(control.message(value));
+                }
+            }
+            Message::MidiInput(message) => {
+                let info = sequencer.layer().info;
+                let keys = sequencer.keys;
+                let target = sequencer.keyboard.input(message, &info, keys);
+                drop(sequencer);
+
+                return match target.filter(|_| keys.transpose) {
+                    Some((root, center)) if keys.follow_octave => Command::batch([
+                        self.apply(Message::NewNote(root)),
+                        self.apply(Message::NewOctave(center)),
+                    ]),
+                    Some((root, _)) => self.apply(Message::NewNote(root)),
+                    None => Command::none(),
+                };
+            }
+            Message::ToggleTranspose => sequencer.keys.transpose = !sequencer.keys.transpose,
+            Message::ToggleFollowOctave => {
+                sequencer.keys.follow_octave = !sequencer.keys.follow_octave
+            }
+            Message::ToggleLatch => sequencer.keys.latch = !sequencer.keys.latch,
+            Message::ToggleLearn => {
+                self.learning = !self.learning;
+                self.armed = None;
+            }
+            Message::ClearBindings => sequencer.bindings.clear(),
+            Message::AddCcOutput => sequencer.cc_outputs.push(CcOutput::default()),
+            Message::RemoveCcOutput(i) => {
+                if i < sequencer.cc_outputs.len() {
+                    sequencer.cc_outputs.remove(i);
+                }
+            }
+            Message::CcOutput(i, output) => {
+                if let Some(current) = sequencer.cc_outputs.get_mut(i) {
+                    *current = CcOutput {
+                        last: None,
+                        ..output
+                    };
+                }
+            }
+            Message::MapMessage(board::Message::Populate(cell)) => {
+                sequencer.map.update(map::Message::Populate(cell))
+            }
+            Message::MapMessage(board::Message::Unpopulate(cell)) => {
+                sequencer.map.update(map::Message::Unpopulate(cell))
+            }
+            Message::MaskMessage(board::Message::Populate(cell)) => sequencer
+                .layer_mut()
+                .mask
+                .update(mask::Message::Check(cell)),
+            Message::MaskMessage(board::Message::Unpopulate(cell)) => sequencer
+                .layer_mut()
+                .mask
+                .update(mask::Message::Uncheck(cell)),
+            Message::TogglePlayback => self.scheduler.send(Event::TogglePlayback),
+            Message::ToggleRecord => match sequencer.stop_recording() {
+                Some(recording) => {
+                    drop(sequencer);
+                    if let Err(e) = recording.save() {
+                        eprintln!("{e}");
+                    }
+                    return Command::none();
+                }
+                None => sequencer.start_recording(take_path(Path::new(&self.session_path))),
+            },
+            Message::OutputPort(port) => {
+                let off = sequencer.midi.all_off();
+                self.port = match self.output.switch(&port, off) {
+                    Ok(()) => port,
+                    Err(e) => {
+                        eprintln!("couldn't open {port}: {e}");
+                        Port::Off
+                    }
+                };
+            }
+            Message::RescanPorts => self.ports = scan_ports(&self.output),
+            Message::ToggleClock => {
+                sequencer.song.clock_out = !sequencer.song.clock_out;
+                self.scheduler.send(Event::Update);
+            }
+            Message::ToggleSync => {
+                sequencer.song.external_sync = !sequencer.song.external_sync;
+                self.scheduler.send(Event::Update);
+            }
+            Message::ToggleLoop => sequencer.toggle_loop(),
+            Message::RandChanged(r) => sequencer.set_randomness(r),
+            Message::RandomizeMap => sequencer.randomize_map(),
+            Message::RandomizeMask => sequencer.randomize_mask(),
+            Message::SeedInput(s) => self.seed_input = s,
+            Message::SeedSubmit => {
+                if let Ok(seed) = self.seed_input.trim().parse() {
+                    sequencer.set_random_seed(seed);
+                }
+                self.seed_input = sequencer.random_seed().to_string();
+            }
+            Message::RulePreset(p) => {
+                sequencer.map.set_rule(p.into());
+                self.rule_input = p.notation().to_string();
+            }
+            Message::RuleInput(s) => self.rule_input = s,
+            Message::Topology(t) => sequencer.map.set_topology(t),
+            Message::GridWidth(w) => {
+                let grid = sequencer.grid;
+                sequencer.set_grid(GridInfo { width: w, ..grid }.clamped())
+            }
+            Message::GridHeight(h) => {
+                let grid = sequencer.grid;
+                sequencer.set_grid(GridInfo { height: h, ..grid }.clamped())
+            }
+            Message::CellSize(c) => {
+                let grid = sequencer.grid;
+                sequencer.set_grid(
+                    GridInfo {
+                        cell_size: c,
+                        ..grid
+                    }
+                    .clamped(),
+                )
+            }
+            Message::RuleSubmit => {
+                if let Ok(rule) = self.rule_input.parse::<Rule>() {
+                    sequencer.map.set_rule(rule);
+                    self.rule_input = rule.to_string();
+                }
+            }
+            Message::ClearMap => sequencer.map.clear(),
+            Message::ClearMask => sequencer.layer_mut().mask.clear(),
+            Message::Reset => sequencer.map.reset(),
+            Message::Save => sequencer.map.save(),
+            Message::SessionPath(p) => self.session_path = p,
+            Message::SaveSession => {
+                if let Err(e) = sequencer.session().save(Path::new(&self.session_path)) {
+                    eprintln!("{e}");
+                }
+            }
+            Message::OpenSession => match Session::open(Path::new(&self.session_path)) {
+                Ok(session) => {
+                    self.rule_input = session.rule.to_string();
+                    self.seed_input = session.random_seed.to_string();
+                    sequencer.load_session(session);
+                    self.scheduler.send(Event::Update);
+                }
+                Err(e) => eprintln!("{e}"),
+            },
+            Message::PatternPath(p) => self.pattern_path = p,
+            Message::PatternOffset(o) => self.pattern_offset = o,
+            Message::ImportMap => {
+                match pattern::import(Path::new(&self.pattern_path), self.pattern_offset) {
+                    Ok(cells) => sequencer.map.add_pattern(cells),
+                    Err(e) => eprintln!("{e}"),
+                }
+            }
+            Message::ImportMask => {
+                match pattern::import(Path::new(&self.pattern_path), self.pattern_offset) {
+                    Ok(cells) => sequencer.layer_mut().mask.add_pattern(cells),
+                    Err(e) => eprintln!("{e}"),
+                }
+            }
+            Message::ExportMap => {
+                let path = Path::new(&self.pattern_path);
+                let rule = sequencer.map.rule();
+                if let Err(e) = pattern::export(path, sequencer.map.cells(), Some(rule)) {
+                    eprintln!("{e}");
+                }
+            }
+            Message::ExportMask => {
+                let path = Path::new(&self.pattern_path);
+                if let Err(e) = pattern::export(path, sequencer.layer().mask.cells(), None) {
+                    eprintln!("{e}");
+                }
+            }
+            Message::SpeedChanged(b) => {
+                sequencer.song.bpm = b.clamp(1, SongInfo::MAX_BPM);
+                self.scheduler.send(Event::Update);
+            }
+            Message::NewDivision(d) => {
+                sequencer.song.divisor = d.clamp(1, SongInfo::MAX_DIVISOR);
+                self.scheduler.send(Event::Update);
+            }
+            Message::LoopLength(l) => sequencer.song.loop_len = l,
+            Message::ProbChanged(p) => sequencer.layer_mut().info.probability = p,
+            Message::NewVMin(v) => sequencer.layer_mut().info.velocity.set_min(v),
+            Message::NewVMax(v) => sequencer.layer_mut().info.velocity.set_max(v),
+            Message::NewGateMin(g) => sequencer.layer_mut().info.gate.set_min(g),
+            Message::NewGateMax(g) => sequencer.layer_mut().info.gate.set_max(g),
+            Message::ChannelChange(c) => sequencer.layer_mut().info.channel = c.min(15),
+            Message::Scale(s) => sequencer.layer_mut().info.scale = s,
+            Message::PitchMode(p) => {
+                let info = &mut sequencer.layer_mut().info;
+                if p == PitchMode::Drums && info.pitch_mode != PitchMode::Drums {
+                    info.channel = Drums::CHANNEL;
+                }
+                info.pitch_mode = p;
+            }
+            Message::DrumLane(l) => self.drum_lane = l,
+            Message::DrumNote(n) => sequencer.layer_mut().info.drums.set_note(self.drum_lane, n),
+            Message::NewOctave(o) => sequencer.layer_mut().info.octave.center = o,
+            Message::OctaveRange(r) => sequencer.layer_mut().info.octave.range = r,
+            Message::NewNote(r) => sequencer.layer_mut().info.root = r,
+            Message::Voices(v) => sequencer.layer_mut().info.voices = v,
+            Message::Program(p) => {
+                let info = &mut sequencer.layer_mut().info;
+                info.program = p;
+                if let Some(program) = p {
+                    let channel = info.channel;
+                    sequencer
+                        .midi
+                        .send_now(MidiMessage::ProgramChange { program, channel });
+                }
+            }
+            Message::SelectLayer(l) => sequencer.select_layer(l),
+            Message::AddLayer => sequencer.add_layer(),
+            Message::RemoveLayer => sequencer.remove_layer(),
+            Message::Quit => return window::close(),
+        }
+
+        let snapshot = sequencer.snapshot();
+        drop(sequencer);
+        self.draw(snapshot);
+
+        Command::none()
+    }
+}
+
+impl Application for CellSeq {
+    type Message = Message;
+    type Theme = Theme;
+    type Executor = executor::Default;
+    type Flags = Flags;
+
+    fn new(flags: Self::Flags) -> (Self, Command<Message>) {
+        let mut rule_input = String::new();
+
+        if let Some(session) = flags.session {
+            rule_input = session.rule.to_string();
+            flags.sequencer.lock().unwrap().load_session(session);
+        }
+        let seed_input = flags.sequencer.lock().unwrap().random_seed().to_string();
+
+        let mut cellseq = Self {
+            sequencer: flags.sequencer,
+            scheduler: flags.scheduler,
+            snapshots: flags.snapshots,
+            inputs: flags.inputs,
+            ports: scan_ports(&flags.output),
+            output: flags.output,
+            port: flags.port,
+            map: Board::default(),
+            mask: Board::default(),
+            rule_input,
+            seed_input,
+            session_path: flags
+                .session_path
+                .unwrap_or_else(|| String::from("session.toml")),
+            pattern_path: String::new(),
+            pattern_offset: Cell::default(),
+            drum_lane: 0,
+            learning: false,
+            armed: None,
+        };
+
+        let snapshot = cellseq.sequencer().snapshot();
+        cellseq.draw(snapshot);
+
+        (cellseq, Command::none())
+    }
+
+    fn title(&self) -> String {
+        String::from("cellseq")
+    }
+
+    fn update(&mut self, message: Message) -> Command<Message> {
+        if self.learning {
+            if let Some(control) = Control::from_message(&message) {
+                self.armed = Some(control);
+            }
+        }
+
+        self.apply(message)
+    }
+
+    fn subscription(&self) -> Subscription<Message> {
+        Subscription::batch([
+            subscription::unfold(
+                "snapshots",
+                self.snapshots.clone(),
+                |snapshots| async move {
+                    match snapshots.recv().await {
+                        Some(snapshot) => (Message::Snapshot(snapshot), snapshots),
+                        None => std::future::pending().await,
+                    }
+                },
+            ),
+            subscription::unfold("inputs", self.inputs.clone(), |inputs| async move {
+                match inputs.recv().await {
+                    Some(message) => (Message::MidiInput(message), inputs),
+                    None => std::future::pending().await,
+                }
+            }),
+        ])
+    }
+
+    fn view(&self) -> Element<'_, Message> {
+        let message = self.control_message();
+
+        let top = top_controls(
+            message.song.is_playing,
+            self.sequencer().is_recording(),
+            &self.ports,
+            &self.port,
+            &self.session_path,
+        );
+
+        let probability_slider = container(
+            column![
+                text(format!("{}", (message.info.probability * 100.0).round())),
+                vertical_slider(0.0..=100.0, message.info.probability * 100.0, |x| {
+                    Message::ProbChanged(x / 100.0)
+                }),
+                text("note density")
+            ]
+            .height(Length::Fixed(350.0))
+            .align_items(Alignment::Center),
+        )
+        .align_y(Vertical::Top);
+
+        let map = container(
+            row![
+                self.map.view().map(Message::MapMessage),
+                probability_slider,
+                self.mask.view().map(Message::MaskMessage)
+            ]
+            .padding(10)
+            .spacing(20),
+        )
+        .align_x(Horizontal::Center);
+
+        let outputs = self.sequencer().cc_outputs.clone();
+        let bottom = bottom_controls(
+            message,
+            outputs,
+            &self.seed_input,
+            &self.rule_input,
+            &self.pattern_path,
+        );
+
+        container(
+            column![top, vertical_space(40), map, bottom]
+                .width(Length::Fill)
+                .align_items(Alignment::Center),
+        )
+        .align_x(Horizontal::Center)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .into()
+    }
+
+    fn theme(&self) -> Theme {
+        Theme::custom(iced::theme::Palette {
+            background: Color::from_rgb8(0x15, 0x15, 0x15),
+            text: Color::from_rgb8(0xD7, 0xD0, 0xC7),
+            primary: Color::from_rgb8(0x9B, 0x64, 0xFB),
+            success: Color::from_rgb8(0x42, 0x71, 0x7B),
+            danger: Color::from_rgb8(0xD2, 0x3D, 0x3D),
+        })
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    ffi::CString,
+    fmt::Display,
+    io::Write,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use alsa::{
+    card,
+    rawmidi::{self, Rawmidi},
+    seq::{self, PortCap, PortType, Seq},
+    Ctl, Direction,
+};
+use thiserror::Error;
+
+use crate::{MidiError, MidiMessage, Timed};
+
+#[derive(Debug, Error)]
+pub enum BackendError {
+    #[error("alsa error: {0}")]
+    Alsa(#[from] alsa::Error),
+    #[cfg(feature = "jack")]
+    #[error("jack error: {0}")]
+    Jack(#[from] jack::Error),
+    #[error(transparent)]
+    Midi(#[from] MidiError),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("output closed")]
+    Closed,
+    #[error("output queue full")]
+    Full,
+    #[error("no output port")]
+    NoPort,
+    #[error("port not found: {0}")]
+    NotFound(String),
+    #[error("unknown backend: {0}")]
+    Unknown(String),
+    #[error("{0} support was not built in")]
+    Unsupported(&'static str),
+}
+
+// somewhere for whole midi messages to go out. the scheduler hands messages over
+// when they are due, so the time is only used by outputs that can place them
+// more finely than that
+pub trait MidiBackend: Send {
+    fn send(&mut self, message: MidiMessage, time: Instant) -> Result<(), BackendError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    Rawmidi,
+    Sequencer,
+    Jack,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 3] = [Backend::Rawmidi, Backend::Sequencer, Backend::Jack];
+
+    pub fn open(self, port: &Port) -> Result<Box<dyn MidiBackend>, BackendError> {
+        if *port == Port::Off {
+            return Err(BackendError::NoPort);
+        }
+
+        match self {
+            Backend::Rawmidi => Ok(Box::new(RawmidiBackend::open(port)?)),
+            Backend::Sequencer => Ok(Box::new(SequencerBackend::open(port)?)),
+            #[cfg(feature = "jack")]
+            Backend::Jack => Ok(Box::new(JackBackend::open(port)?)),
+            #[cfg(not(feature = "jack"))]
+            Backend::Jack => Err(BackendError::Unsupported("jack")),
+        }
+    }
+
+    // the port opened when none is asked for
+    pub fn default_port(self) -> Port {
+        match self {
+            Backend::Rawmidi => Port::Named(String::from("virtual")),
+            _ => Port::Own,
+        }
+    }
+
+    // every port that can be sent to, the default first
+    pub fn ports(self) -> Result<Vec<Port>, BackendError> {
+        let mut ports = vec![self.default_port()];
+        match self {
+            Backend::Rawmidi => ports.extend(RawmidiBackend::ports()?),
+            Backend::Sequencer => ports.extend(SequencerBackend::ports()?),
+            #[cfg(feature = "jack")]
+            Backend::Jack => ports.extend(JackBackend::ports()?),
+            #[cfg(not(feature = "jack"))]
+            Backend::Jack => return Err(BackendError::Unsupported("jack")),
+        }
+
+        Ok(ports)
+    }
+}
+
+impl FromStr for Backend {
+    type Err = BackendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Backend::ALL
+            .into_iter()
+            .find(|backend| backend.to_string() == s)
+            .ok_or(BackendError::Unknown(s.to_string()))
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Backend::Rawmidi => "rawmidi",
+            Backend::Sequencer => "seq",
+            Backend::Jack => "jack",
+        };
+
+        write!(f, "{str}")
+    }
+}
+
+// where the messages of a backend go
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Port {
+    // nothing is sent, for when there is nowhere to send to
+    #[default]
+    Off,
+    // the port the backend opens for other programs to connect to
+    Own,
+    // a device or port of another program, by name. the backends that open a
+    // port of their own connect it to this one
+    Named(String),
+}
+
+impl Display for Port {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Port::Off => write!(f, "no output"),
+            Port::Own => write!(f, "cellseq"),
+            Port::Named(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+// the backend the midi thread writes to, which can be moved to another port
+// while running. anything sent while no port is open is dropped
+#[derive(Clone)]
+pub struct Output {
+    backend: Backend,
+    sink: Arc<Mutex<Option<Box<dyn MidiBackend>>>>,
+}
+
+impl Output {
+    pub fn new(backend: Backend) -> Self {
+        Self {
+            backend,
+            sink: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    // on failure the output is left with no port open
+    pub fn open(&self, port: &Port) -> Result<(), BackendError> {
+        let mut sink = self.sink.lock().unwrap();
+        *sink = None;
+        if *port != Port::Off {
+            *sink = Some(self.backend.open(port)?);
+        }
+        Ok(())
+    }
+
+    // sends the note offs to the port being left before opening the new one
+    pub fn switch(&self, port: &Port, off: Vec<MidiMessage>) -> Result<(), BackendError> {
+        if let Some(sink) = self.sink.lock().unwrap().as_mut() {
+            for message in off {
+                sink.send(message, Instant::now())?;
+            }
+        }
+        self.open(port)
+    }
+
+    pub fn send(&self, timed: Timed) -> Result<(), BackendError> {
+        match self.sink.lock().unwrap().as_mut() {
+            Some(sink) => sink.send(timed.message, timed.time),
+            None => Ok(()),
+        }
+    }
+}
+
+fn encode(message: MidiMessage) -> Result<Vec<u8>, MidiError> {
+    Ok(message.as_bytes()?.into_iter().flatten().collect())
+}
+
+// an alsa rawmidi device, the virtual one by default
+pub struct RawmidiBackend {
+    rawmidi: Rawmidi,
+}
+
+impl RawmidiBackend {
+    pub fn open(port: &Port) -> Result<Self, BackendError> {
+        let name = match port {
+            Port::Named(name) => name.as_str(),
+            _ => "virtual",
+        };
+
+        Ok(Self {
+            rawmidi: Rawmidi::new(name, Direction::Playback, false)?,
+        })
+    }
+
+    // the output subdevices of every card
+    pub fn ports() -> Result<Vec<Port>, BackendError> {
+        let mut ports = Vec::new();
+        for card in card::Iter::new() {
+            let card = card?;
+            let ctl = Ctl::from_card(&card, false)?;
+            for info in rawmidi::Iter::new(&ctl) {
+                let info = info?;
+                if info.get_stream() == Direction::Playback {
+                    ports.push(Port::Named(format!(
+                        "hw:{},{},{}",
+                        card.get_index(),
+                        info.get_device(),
+                        info.get_subdevice()
+                    )));
+                }
+            }
+        }
+
+        Ok(ports)
+    }
+}
+
+impl MidiBackend for RawmidiBackend {
+    fn send(&mut self, message: MidiMessage, _time: Instant) -> Result<(), BackendError> {
+        self.rawmidi.io().write_all(&encode(message)?)?;
+        Ok(())
+    }
+}
+
+// a named port on the alsa sequencer, to be connected with aconnect
+pub struct SequencerBackend {
+    seq: Seq,
+    port: i32,
+}
+
+impl SequencerBackend {
+    pub fn open(port: &Port) -> Result<Self, BackendError> {
+        let seq = Seq::open(None, Some(Direction::Playback), false)?;
+        seq.set_client_name(&CString::new("cellseq").unwrap())?;
+        let own = seq.create_simple_port(
+            &CString::new("output").unwrap(),
+            PortCap::READ | PortCap::SUBS_READ,
+            PortType::MIDI_GENERIC | PortType::APPLICATION,
+        )?;
+
+        if let Port::Named(name) = port {
+            let dest = destinations(&seq)?
+                .into_iter()
+                .find(|(dest, _)| dest == name)
+                .ok_or(BackendError::NotFound(name.clone()))?
+                .1;
+            let subscription = seq::PortSubscribe::empty()?;
+            subscription.set_sender(seq::Addr {
+                client: seq.client_id()?,
+                port: own,
+            });
+            subscription.set_dest(dest);
+            seq.subscribe_port(&subscription)?;
+        }
+
+        Ok(Self { seq, port: own })
+    }
+
+    pub fn ports() -> Result<Vec<Port>, BackendError> {
+        let seq = Seq::open(None, None, false)?;
+        Ok(destinations(&seq)?
+            .into_iter()
+            .map(|(name, _)| Port::Named(name))
+            .collect())
+    }
+}
+
+// the sequencer ports other clients can be connected to, as client:port names
+fn destinations(seq: &Seq) -> Result<Vec<(String, seq::Addr)>, BackendError> {
+    let own = seq.client_id()?;
+    let mut destinations = Vec::new();
+    for client in seq::ClientIter::new(seq) {
+        // the system client only has the timer and announcements
+        if client.get_client() == 0 || client.get_client() == own {
+            continue;
+        }
+        for port in seq::PortIter::new(seq, client.get_client()) {
+            let caps = port.get_capability();
+            if caps.contains(PortCap::WRITE | PortCap::SUBS_WRITE) {
+                let name = format!("{}:{}", client.get_name()?, port.get_name()?);
+                destinations.push((name, port.addr()));
+            }
+        }
+    }
+
+    Ok(destinations)
+}
+
+impl MidiBackend for SequencerBackend {
+    fn send(&mut self, message: MidiMessage, _time: Instant) -> Result<(), BackendError> {
+        let bytes = encode(message)?;
+        let mut encoder = seq::MidiEvent::new(bytes.len() as u32)?;
+        if let (_, Some(mut event)) = encoder.encode(&bytes)? {
+            event.set_source(self.port);
+            event.set_subs();
+            event.set_direct();
+            self.seq.event_output_direct(&mut event)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "jack")]
+type JackProcess = Box<dyn FnMut(&jack::Client, &jack::ProcessScope) -> jack::Control + Send>;
+
+// a midi out port on a jack client, written from the process callback
+#[cfg(feature = "jack")]
+pub struct JackBackend {
+    _client: jack::AsyncClient<(), jack::ClosureProcessHandler<JackProcess>>,
+    messages: std::sync::mpsc::SyncSender<Vec<u8>>,
+}
+
+#[cfg(feature = "jack")]
+impl JackBackend {
+    pub fn open(port: &Port) -> Result<Self, BackendError> {
+        let (client, _) = jack::Client::new("cellseq", jack::ClientOptions::NO_START_SERVER)?;
+        let mut output = client.register_port("output", jack::MidiOut)?;
+        let own = output.name()?;
+        let (send, receive) = std::sync::mpsc::sync_channel::<Vec<u8>>(256);
+
+        // everything that came in since the last cycle goes out at its start
+        let process: JackProcess = Box::new(move |_, scope| {
+            let mut writer = output.writer(scope);
+            for bytes in receive.try_iter() {
+                let _ = writer.write(&jack::RawMidi {
+                    time: 0,
+                    bytes: &bytes,
+                });
+            }
+            jack::Control::Continue
+        });
+        let client = client.activate_async((), jack::ClosureProcessHandler::new(process))?;
+        if let Port::Named(name) = port {
+            client.as_client().connect_ports_by_name(&own, name)?;
+        }
+
+        Ok(Self {
+            _client: client,
+            messages: send,
+        })
+    }
+
+    pub fn ports() -> Result<Vec<Port>, BackendError> {
+        let (client, _) = jack::Client::new("cellseq-ports", jack::ClientOptions::NO_START_SERVER)?;
+        Ok(client
+            .ports(None, Some("midi"), jack::PortFlags::IS_INPUT)
+            .into_iter()
+            .map(Port::Named)
+            .collect())
+    }
+}
+
+#[cfg(feature = "jack")]
+impl MidiBackend for JackBackend {
+    fn send(&mut self, message: MidiMessage, _time: Instant) -> Result<(), BackendError> {
+        // the process callback may have stopped, and waiting on it would hold up
+        // the midi thread
+        self.messages
+            .try_send(encode(message)?)
+            .map_err(|e| match e {
+                std::sync::mpsc::TrySendError::Full(_) => BackendError::Full,
+                std::sync::mpsc::TrySendError::Disconnected(_) => BackendError::Closed,
+            })
+    }
+}
+
+// keeps everything sent in memory, for tests. the handle is cloned before it is
+// boxed as a backend so the messages can be read back
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    messages: Arc<Mutex<Vec<Timed>>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Recorder {
+    pub fn messages(&self) -> Vec<Timed> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl MidiBackend for Recorder {
+    // messages that couldn't go out on the wire are refused here too
+    fn send(&mut self, message: MidiMessage, time: Instant) -> Result<(), BackendError> {
+        message.as_bytes()?;
+        self.messages.lock().unwrap().push(Timed { message, time });
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use iced::{
+    mouse::{self, Button::Left, Event::ButtonPressed},
+    widget::canvas::{
+        event::{self, Event},
+        Cache, Canvas, Cursor, Geometry, Path, Program,
+    },
+    {Color, Element, Length, Point, Rectangle, Size, Theme},
+};
+
+use crate::{Cell, CellMap, GridInfo};
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Populate(Cell),
+    Unpopulate(Cell),
+}
+
+impl Cell {
+    fn at(position: Point, size: usize) -> Cell {
+        let i = (position.y / size as f32).ceil() as isize;
+        let j = (position.x / size as f32).ceil() as isize;
+
+        Cell {
+            i: i.saturating_sub(1),
+            j: j.saturating_sub(1),
+        }
+    }
+}
+
+impl GridInfo {
+    fn pixels(&self) -> (f32, f32) {
+        (
+            (self.width * self.cell_size) as f32,
+            (self.height * self.cell_size) as f32,
+        )
+    }
+}
+
+// the drawn copy of a map or mask, refreshed from sequencer snapshots
+#[derive(Debug, Default)]
+pub struct Board {
+    cells: CellMap,
+    hits: CellMap,
+    grid: GridInfo,
+    cache: Cache,
+}
+
+impl Board {
+    pub fn set(&mut self, cells: CellMap, hits: CellMap, grid: GridInfo) {
+        self.cells = cells;
+        self.hits = hits;
+        self.grid = grid;
+        self.cache.clear();
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let (width, height) = self.grid.pixels();
+        Canvas::new(self)
+            .width(Length::Fixed(width))
+            .height(Length::Fixed(height))
+            .into()
+    }
+}
+
+impl Program<Message> for Board {
+    type State = bool;
+
+    fn update(
+        &self,
+        _interaction: &mut bool,
+        event: Event,
+        bounds: Rectangle,
+        cursor: Cursor,
+    ) -> (event::Status, Option<Message>) {
+        if let Some(position) = cursor.position_in(&bounds) {
+            if let Event::Mouse(ButtonPressed(Left)) = event {
+                let cell = Cell::at(position, self.grid.cell_size);
+                return (
+                    event::Status::Captured,
+                    if self.cells.contains(&cell) {
+                        Some(Message::Unpopulate(cell))
+                    } else {
+                        Some(Message::Populate(cell))
+                    },
+                );
+            }
+        }
+
+        (event::Status::Ignored, None)
+    }
+
+    fn draw(
+        &self,
+        _interaction: &bool,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: Cursor,
+    ) -> Vec<Geometry> {
+        vec![self.cache.draw(bounds.size(), |frame| {
+            let background = Path::rectangle(Point::ORIGIN, frame.size());
+            frame.fill(&background, Color::from_rgb8(0x30, 0x30, 0x30));
+
+            frame.with_save(|frame| {
+                frame.scale(self.grid.cell_size as f32);
+
+                self.grid
+                    .cells()
+                    .filter(|cell| self.cells.contains(cell))
+                    .for_each(|cell| {
+                        frame.fill_rectangle(
+                            Point::new(cell.j as f32, cell.i as f32),
+                            Size::UNIT,
+                            if self.hits.contains(&cell) {
+                                Color::from_rgb8(0x42, 0x71, 0x7B)
+                            } else {
+                                Color::from_rgb8(0xD7, 0xD0, 0xC7)
+                            },
+                        );
+                    })
+            });
+        })]
+    }
+
+    fn mouse_interaction(
+        &self,
+        _interaction: &bool,
+        _bounds: Rectangle,
+        _cursor: Cursor,
+    ) -> mouse::Interaction {
+        mouse::Interaction::default()
+    }
+}
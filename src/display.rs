@@ -3,27 +3,47 @@ use iced::{
     theme,
     widget::{
         button, checkbox, column, container, horizontal_space, pick_list, row, slider, text,
-        vertical_slider, vertical_space,
+        text_input, vertical_slider, vertical_space,
     },
     Alignment, Element, Length,
 };
 
 use crate::{
-    music::{Accidental, Root, RootNote, Scale},
-    Message, MidiInfo, SongInfo,
+    map::Topology,
+    music::{Accidental, Drums, Gate, PitchMode, Root, RootNote, Scale},
+    rule::{Rule, RulePreset},
+    stats::Metric,
+    CcOutput, Cell, Control, GridInfo, KeyboardInfo, Message, MidiInfo, Port, SongInfo,
 };
 
 #[derive(Default, Copy, Clone, Debug)]
 pub struct ControlMessage {
     pub randomness: f32,
+    pub rule: Rule,
+    pub topology: Topology,
+    pub grid: GridInfo,
+    pub pattern_offset: Cell,
+    pub drum_lane: usize,
+    pub learning: bool,
+    pub armed: Option<Control>,
     pub info: MidiInfo,
+    pub layer: usize,
+    pub layers: usize,
     pub song: SongInfo,
+    pub keys: KeyboardInfo,
 }
 
-pub fn top_controls<'a>(is_playing: bool) -> Element<'a, Message> {
+pub fn top_controls<'a>(
+    is_playing: bool,
+    recording: bool,
+    ports: &'a [Port],
+    port: &Port,
+    session_path: &'a str,
+) -> Element<'a, Message> {
     let play_button = container(
         row![
             button(if is_playing { "stop" } else { "play" }).on_press(Message::TogglePlayback),
+            checkbox("record", recording, |_| Message::ToggleRecord),
             button("save map")
                 .on_press(Message::Save)
                 .style(theme::Button::Positive),
@@ -37,14 +57,31 @@ pub fn top_controls<'a>(is_playing: bool) -> Element<'a, Message> {
                 .on_press(Message::ClearMask)
                 .style(theme::Button::Destructive),
         ]
+        .align_items(Alignment::Center)
         .spacing(10),
     )
     .align_x(Horizontal::Left);
 
     let other_controls = container(
-        button("quit")
-            .on_press(Message::Quit)
-            .style(theme::Button::Destructive),
+        row![
+            pick_list(ports, Some(port.clone()), Message::OutputPort).width(Length::Fixed(160.0)),
+            button("rescan")
+                .on_press(Message::RescanPorts)
+                .style(theme::Button::Secondary),
+            text_input("session file", session_path)
+                .on_input(Message::SessionPath)
+                .width(Length::Fixed(200.0)),
+            button("open session")
+                .on_press(Message::OpenSession)
+                .style(theme::Button::Secondary),
+            button("save session")
+                .on_press(Message::SaveSession)
+                .style(theme::Button::Positive),
+            button("quit")
+                .on_press(Message::Quit)
+                .style(theme::Button::Destructive),
+        ]
+        .spacing(10),
     )
     .align_x(Horizontal::Right);
 
@@ -54,12 +91,22 @@ pub fn top_controls<'a>(is_playing: bool) -> Element<'a, Message> {
         .into()
 }
 
-pub fn bottom_controls<'a>(message: ControlMessage) -> Element<'a, Message> {
+pub fn bottom_controls<'a>(
+    message: ControlMessage,
+    outputs: Vec<CcOutput>,
+    seed_input: &'a str,
+    rule_input: &'a str,
+    pattern_path: &'a str,
+) -> Element<'a, Message> {
     container(
         column![
-            randomize_section(message.randomness),
+            randomize_section(message.randomness, seed_input),
+            map_section(message, rule_input),
+            grid_section(message.grid),
+            pattern_section(message.pattern_offset, pattern_path),
             vertical_space(40),
-            music_controls(message)
+            music_controls(message),
+            cc_section(outputs)
         ]
         .align_items(Alignment::Center)
         .padding(10)
@@ -73,13 +120,17 @@ fn music_controls<'a>(message: ControlMessage) -> Element<'a, Message> {
     container(
         row![
             column![
+                layer_selector(message),
                 scale_selector(message),
                 row![song_section(message), midi_section(message),]
                     .padding(10)
                     .spacing(40),
+                keyboard_section(message.keys),
+                learn_section(message.learning, message.armed),
             ]
             .align_items(Alignment::Center),
-            velocity_sliders(message)
+            velocity_sliders(message),
+            gate_sliders(message)
         ]
         .padding(10)
         .spacing(40),
@@ -87,6 +138,124 @@ fn music_controls<'a>(message: ControlMessage) -> Element<'a, Message> {
     .into()
 }
 
+fn cc_section<'a>(outputs: Vec<CcOutput>) -> Element<'a, Message> {
+    let rows = outputs.into_iter().enumerate().map(|(i, output)| {
+        let controller = output.controller;
+        let channel = output.channel;
+        row![
+            pick_list(&Metric::ALL[..], Some(output.metric), move |metric| {
+                Message::CcOutput(i, CcOutput { metric, ..output })
+            })
+            .width(Length::Fixed(140.0)),
+            text("controller"),
+            button("-").on_press(Message::CcOutput(
+                i,
+                CcOutput {
+                    controller: controller.saturating_sub(1),
+                    ..output
+                }
+            )),
+            text(format!("{controller}")),
+            button("+").on_press(Message::CcOutput(
+                i,
+                CcOutput {
+                    controller: controller.saturating_add(1).min(127),
+                    ..output
+                }
+            )),
+            text("channel"),
+            button("-").on_press(Message::CcOutput(
+                i,
+                CcOutput {
+                    channel: channel.saturating_sub(1),
+                    ..output
+                }
+            )),
+            text(format!("{}", channel + 1)),
+            button("+").on_press(Message::CcOutput(
+                i,
+                CcOutput {
+                    channel: channel.saturating_add(1).min(15),
+                    ..output
+                }
+            )),
+            button("remove")
+                .on_press(Message::RemoveCcOutput(i))
+                .style(theme::Button::Destructive),
+        ]
+        .align_items(Alignment::Center)
+        .spacing(10)
+        .into()
+    });
+
+    container(
+        column(rows.collect())
+            .push(button("add cc output").on_press(Message::AddCcOutput))
+            .align_items(Alignment::Center)
+            .spacing(10),
+    )
+    .into()
+}
+
+fn layer_selector<'a>(message: ControlMessage) -> Element<'a, Message> {
+    let layer = message.layer;
+    container(
+        row![
+            text("layer"),
+            button("-").on_press(Message::SelectLayer(layer.saturating_sub(1))),
+            text(format!("{} of {}", layer + 1, message.layers)),
+            button("+").on_press(Message::SelectLayer(layer + 1)),
+            button("add layer").on_press(Message::AddLayer),
+            button("remove layer")
+                .on_press(Message::RemoveLayer)
+                .style(theme::Button::Destructive),
+        ]
+        .align_items(Alignment::Center)
+        .spacing(10),
+    )
+    .align_x(Horizontal::Center)
+    .into()
+}
+
+fn keyboard_section<'a>(keys: KeyboardInfo) -> Element<'a, Message> {
+    container(
+        row![
+            checkbox("transpose from keyboard", keys.transpose, |_| {
+                Message::ToggleTranspose
+            }),
+            checkbox("follow octave", keys.follow_octave, |_| {
+                Message::ToggleFollowOctave
+            }),
+            checkbox("latch", keys.latch, |_| Message::ToggleLatch),
+        ]
+        .spacing(20),
+    )
+    .align_x(Horizontal::Center)
+    .into()
+}
+
+fn learn_section<'a>(learning: bool, armed: Option<Control>) -> Element<'a, Message> {
+    let status = match armed {
+        Some(control) => format!("move a controller for {control}"),
+        None if learning => String::from("pick a control to bind"),
+        None => String::new(),
+    };
+
+    container(
+        row![
+            checkbox("midi learn", learning, |_| Message::ToggleLearn),
+            text(status),
+            button("clear bindings")
+                .on_press(Message::ClearBindings)
+                .style(theme::Button::Destructive),
+        ]
+        .align_items(Alignment::Center)
+        .spacing(20),
+    )
+    .align_x(Horizontal::Center)
+    .into()
+}
+
 fn song_section<'a>(message: ControlMessage) -> Element<'a, Message> {
     container(
         row![song_params(), song_vals(message)]
@@ -112,6 +281,8 @@ fn song_params<'a>() -> Element<'a, Message> {
             text("note division"),
             text("number of steps"),
             text("loop section"),
+            text("send clock"),
+            text("external sync"),
         ]
         .align_items(Alignment::End)
         .padding(10)
@@ -149,6 +320,8 @@ fn song_vals<'a>(message: ControlMessage) -> Element<'a, Message> {
             .align_items(Alignment::Center)
             .spacing(10),
             checkbox("", message.song.is_looping, |_| { Message::ToggleLoop }),
+            checkbox("", message.song.clock_out, |_| { Message::ToggleClock }),
+            checkbox("", message.song.external_sync, |_| { Message::ToggleSync }),
         ]
         .align_items(Alignment::Center)
         .padding(10)
@@ -164,6 +337,7 @@ fn midi_params<'a>() -> Element<'a, Message> {
             text("octave range"),
             text("number of voices"),
             text("midi channel"),
+            text("program"),
         ]
         .align_items(Alignment::End)
         .padding(10)
@@ -210,6 +384,22 @@ fn midi_vals<'a>(message: ControlMessage) -> Element<'a, Message> {
                     message.info.channel.saturating_add(1)
                 )),
             ]
+            .spacing(10),
+            row![
+                button("-").on_press(Message::Program(
+                    message.info.program.and_then(|p| p.checked_sub(1))
+                )),
+                text(match message.info.program {
+                    Some(program) => format!("{}", program + 1),
+                    None => String::from("none"),
+                }),
+                button("+").on_press(Message::Program(Some(
+                    message
+                        .info
+                        .program
+                        .map_or(0, |p| p.saturating_add(1).min(127))
+                ))),
+            ]
             .spacing(10)
         ]
         .align_items(Alignment::Center)
@@ -219,7 +409,7 @@ fn midi_vals<'a>(message: ControlMessage) -> Element<'a, Message> {
     .into()
 }
 
-fn randomize_section<'a>(r: f32) -> Element<'a, Message> {
+fn randomize_section<'a>(r: f32, seed: &'a str) -> Element<'a, Message> {
     container(
         row![
             button("randomize map")
@@ -237,12 +427,100 @@ fn randomize_section<'a>(r: f32) -> Element<'a, Message> {
             button("randomize mask")
                 .on_press(Message::RandomizeMask)
                 .style(theme::Button::Primary),
+            text("seed"),
+            text_input("seed", seed)
+                .on_input(Message::SeedInput)
+                .on_submit(Message::SeedSubmit)
+                .width(Length::Fixed(180.0)),
         ]
+        .align_items(Alignment::Center)
         .spacing(20),
     )
     .into()
 }
 
+fn map_section<'a>(message: ControlMessage, input: &'a str) -> Element<'a, Message> {
+    let rule = message.rule;
+    container(
+        row![
+            pick_list(
+                &Topology::ALL[..],
+                Some(message.topology),
+                Message::Topology
+            )
+            .width(Length::Fixed(110.0)),
+            text("rule"),
+            pick_list(
+                &RulePreset::ALL[..],
+                RulePreset::matching(rule),
+                Message::RulePreset
+            )
+            .placeholder("custom")
+            .width(Length::Fixed(180.0)),
+            text_input("B3/S23", input)
+                .on_input(Message::RuleInput)
+                .on_submit(Message::RuleSubmit)
+                .width(Length::Fixed(160.0)),
+            text(format!("{rule}")),
+        ]
+        .align_items(Alignment::Center)
+        .spacing(20),
+    )
+    .into()
+}
+
+fn grid_section<'a>(grid: GridInfo) -> Element<'a, Message> {
+    container(
+        row![
+            text("width"),
+            button("-").on_press(Message::GridWidth(grid.width.saturating_sub(1))),
+            text(format!("{}", grid.width)),
+            button("+").on_press(Message::GridWidth(grid.width.saturating_add(1))),
+            text("height"),
+            button("-").on_press(Message::GridHeight(grid.height.saturating_sub(1))),
+            text(format!("{}", grid.height)),
+            button("+").on_press(Message::GridHeight(grid.height.saturating_add(1))),
+            text("cell size"),
+            button("-").on_press(Message::CellSize(grid.cell_size.saturating_sub(1))),
+            text(format!("{}", grid.cell_size)),
+            button("+").on_press(Message::CellSize(grid.cell_size.saturating_add(1))),
+        ]
+        .align_items(Alignment::Center)
+        .spacing(10),
+    )
+    .into()
+}
+
+fn pattern_section<'a>(offset: Cell, path: &'a str) -> Element<'a, Message> {
+    let Cell { i, j } = offset;
+    container(
+        row![
+            text_input("pattern.rle", path)
+                .on_input(Message::PatternPath)
+                .width(Length::Fixed(200.0)),
+            text("row"),
+            button("-").on_press(Message::PatternOffset(Cell { i: i - 1, j })),
+            text(format!("{i}")),
+            button("+").on_press(Message::PatternOffset(Cell { i: i + 1, j })),
+            text("column"),
+            button("-").on_press(Message::PatternOffset(Cell { i, j: j - 1 })),
+            text(format!("{j}")),
+            button("+").on_press(Message::PatternOffset(Cell { i, j: j + 1 })),
+            button("import map").on_press(Message::ImportMap),
+            button("import mask").on_press(Message::ImportMask),
+            button("export map")
+                .on_press(Message::ExportMap)
+                .style(theme::Button::Positive),
+            button("export mask")
+                .on_press(Message::ExportMask)
+                .style(theme::Button::Positive),
+        ]
+        .align_items(Alignment::Center)
+        .spacing(10),
+    )
+    .into()
+}
+
 fn velocity_sliders<'a>(message: ControlMessage) -> Element<'a, Message> {
     container(
         column![
@@ -267,11 +545,50 @@ fn velocity_sliders<'a>(message: ControlMessage) -> Element<'a, Message> {
     .into()
 }
 
+fn gate_sliders<'a>(message: ControlMessage) -> Element<'a, Message> {
+    container(
+        column![
+            text("gate length"),
+            row![
+                column![
+                    text(format!("{:.3}", message.info.gate.max())),
+                    vertical_slider(
+                        Gate::MIN..=Gate::MAX,
+                        message.info.gate.max(),
+                        Message::NewGateMax
+                    )
+                    .step(Gate::MIN),
+                    text("max")
+                ],
+                column![
+                    text(format!("{:.3}", message.info.gate.min())),
+                    vertical_slider(
+                        Gate::MIN..=Gate::MAX,
+                        message.info.gate.min(),
+                        Message::NewGateMin
+                    )
+                    .step(Gate::MIN),
+                    text("min")
+                ],
+            ]
+            .spacing(20.0)
+        ]
+        .height(Length::Fixed(300.0))
+        .spacing(10),
+    )
+    .into()
+}
+
 fn scale_selector<'a>(message: ControlMessage) -> Element<'a, Message> {
     let scale = message.info.scale;
+    let pitch_mode = message.info.pitch_mode;
     let note = message.info.root.note;
     let accidental = message.info.root.accidental;
-    container(
+
+    // drum layers play fixed notes, so the lanes take the place of the scale
+    let notes: Element<'a, Message> = if pitch_mode == PitchMode::Drums {
+        drum_lanes(message)
+    } else {
         row![
             pick_list(&RootNote::ALL[..], Some(note), move |note| {
                 Message::NewNote(Root { note, accidental })
@@ -283,8 +600,36 @@ fn scale_selector<'a>(message: ControlMessage) -> Element<'a, Message> {
             .width(Length::Fixed(90.0)),
             pick_list(&Scale::ALL[..], Some(scale), Message::Scale).width(Length::Fixed(160.0)),
         ]
+        .spacing(10)
+        .into()
+    };
+
+    container(
+        row![
+            notes,
+            pick_list(&PitchMode::ALL[..], Some(pitch_mode), Message::PitchMode)
+                .width(Length::Fixed(160.0)),
+        ]
         .spacing(10),
     )
     .align_x(Horizontal::Center)
     .into()
 }
+
+fn drum_lanes<'a>(message: ControlMessage) -> Element<'a, Message> {
+    let lane = message.drum_lane;
+    let note = message.info.drums.note(lane);
+    row![
+        text("lane"),
+        button("-").on_press(Message::DrumLane((lane + Drums::LANES - 1) % Drums::LANES)),
+        text(format!("{}", lane + 1)),
+        button("+").on_press(Message::DrumLane((lane + 1) % Drums::LANES)),
+        text("note"),
+        button("-").on_press(Message::DrumNote(note.saturating_sub(1))),
+        text(format!("{note}")),
+        button("+").on_press(Message::DrumNote(note.saturating_add(1))),
+    ]
+    .align_items(Alignment::Center)
+    .spacing(10)
+    .into()
+}
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{music::Root, Feed, MidiInfo, MidiMessage};
+
+// note and controller messages arriving on the midi input, for the gui to act on
+pub type Inputs = Feed<MidiMessage>;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyboardInfo {
+    // the root of the selected layer follows the last key played
+    pub transpose: bool,
+    // the octave center follows along with the root
+    pub follow_octave: bool,
+    // keeps the last key played after it is let go instead of going back
+    pub latch: bool,
+}
+
+// the keys currently held on an external keyboard, oldest first
+#[derive(Debug, Default)]
+pub struct Keyboard {
+    held: Vec<u8>,
+    // the root and octave center from before the first key, to go back to
+    home: Option<(Root, u8)>,
+}
+
+impl Keyboard {
+    pub fn held(&self) -> &[u8] {
+        &self.held
+    }
+
+    // tracks the keys in a note message, returning the root and octave center
+    // the layer should move to if that changed
+    pub fn input(
+        &mut self,
+        message: MidiMessage,
+        info: &MidiInfo,
+        keys: KeyboardInfo,
+    ) -> Option<(Root, u8)> {
+        match message {
+            MidiMessage::On { note, velocity, .. } if velocity > 0 => {
+                if self.held.is_empty() && !keys.latch {
+                    self.home = Some((info.root, info.octave.center));
+                }
+                self.held.retain(|held| *held != note);
+                self.held.push(note);
+                Some(transpose(note))
+            }
+            // a note on without velocity is a note off
+            MidiMessage::On { note, .. } | MidiMessage::Off { note, .. } => {
+                self.held.retain(|held| *held != note);
+                match self.held.last() {
+                    Some(last) => Some(transpose(*last)),
+                    None if keys.latch => None,
+                    None => self.home.take(),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn transpose(note: u8) -> (Root, u8) {
+    let root = Root::from_note(note);
+    (root, note.saturating_sub(root.into()) / 12)
+}
@@ -0,0 +1,60 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+// the controls that can be bound to an incoming controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Control {
+    Bpm,
+    Divisor,
+    LoopLength,
+    Probability,
+    Randomness,
+    VelocityMin,
+    VelocityMax,
+    GateMin,
+    GateMax,
+    OctaveCenter,
+    OctaveRange,
+    Voices,
+}
+
+impl Display for Control {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Control::Bpm => "bpm",
+            Control::Divisor => "note division",
+            Control::LoopLength => "number of steps",
+            Control::Probability => "note density",
+            Control::Randomness => "randomness",
+            Control::VelocityMin => "velocity min",
+            Control::VelocityMax => "velocity max",
+            Control::GateMin => "gate min",
+            Control::GateMax => "gate max",
+            Control::OctaveCenter => "center octave",
+            Control::OctaveRange => "octave range",
+            Control::Voices => "number of voices",
+        };
+
+        write!(f, "{str}")
+    }
+}
+
+// a controller on a midi channel, bound to one control
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Binding {
+    pub channel: u8,
+    pub controller: u8,
+    pub control: Control,
+}
+
+impl Binding {
+    pub fn clamped(self) -> Self {
+        Self {
+            channel: self.channel.min(15),
+            controller: self.controller.min(127),
+            ..self
+        }
+    }
+}
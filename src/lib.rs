@@ -1,48 +1,64 @@
-use iced::{
-    alignment::{Horizontal, Vertical},
-    executor,
-    theme::Theme,
-    time,
-    widget::{column, container, row, text, vertical_slider, vertical_space},
-    window, Alignment, Color, {Application, Command, Element, Length, Point, Subscription},
-};
-
 use itertools::Itertools;
-use music::Scale;
 use rustc_hash::FxHashSet;
-use std::time::{Duration, Instant};
-
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "gui")]
+mod app;
+mod backend;
+#[cfg(feature = "gui")]
+mod board;
+#[cfg(feature = "gui")]
 mod display;
-mod map;
-mod mask;
+mod keyboard;
+mod learn;
+pub mod map;
+pub mod mask;
 mod midi;
 mod music;
-
-use display::*;
-use map::*;
-use mask::*;
+pub mod pattern;
+mod record;
+mod rule;
+mod scheduler;
+mod sequencer;
+mod session;
+mod stats;
+
+#[cfg(feature = "gui")]
+pub use app::*;
+pub use backend::*;
+pub use keyboard::*;
+pub use learn::*;
+pub use map::{Map, Topology};
+pub use mask::Mask;
 pub use midi::*;
-use music::*;
+pub use music::*;
+pub use record::*;
+pub use rule::*;
+pub use scheduler::*;
+pub use sequencer::*;
+pub use session::*;
+pub use stats::*;
 
 pub type CellMap = FxHashSet<Cell>;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct Cell {
     i: isize,
     j: isize,
 }
 
 impl Cell {
-    const SIZE: usize = 16;
+    // row i counts down from the top and column j across from the left
+    pub fn new(i: isize, j: isize) -> Cell {
+        Cell { i, j }
+    }
 
-    fn at(position: Point) -> Cell {
-        let i = (position.y / Cell::SIZE as f32).ceil() as isize;
-        let j = (position.x / Cell::SIZE as f32).ceil() as isize;
+    pub fn i(&self) -> isize {
+        self.i
+    }
 
-        Cell {
-            i: i.saturating_sub(1),
-            j: j.saturating_sub(1),
-        }
+    pub fn j(&self) -> isize {
+        self.j
     }
 
     fn cluster(cell: Cell) -> impl Iterator<Item = Cell> {
@@ -52,250 +68,111 @@ impl Cell {
         rows.cartesian_product(columns).map(|(i, j)| Cell { i, j })
     }
 
-    fn neighbors(cell: Cell) -> impl Iterator<Item = Cell> {
-        Cell::cluster(cell).filter(move |candidate| *candidate != cell)
+    fn neighbors(cell: Cell, topology: Topology, grid: GridInfo) -> impl Iterator<Item = Cell> {
+        Cell::cluster(cell)
+            .filter(move |candidate| *candidate != cell)
+            .filter_map(move |candidate| topology.place(candidate, grid))
     }
 }
 
-#[derive(Default)]
-pub struct CellSeq {
-    map: Map,
-    mask: Mask,
-    midi: MidiLink,
-    song: SongInfo,
-    info: MidiInfo,
-}
-
-#[derive(Copy, Clone, Debug)]
-pub struct SongInfo {
-    pub is_playing: bool,
-    pub bpm: usize,
-    pub divisor: usize,
-    pub is_looping: bool,
-    pub loop_len: usize,
-    pub step_num: usize,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GridInfo {
+    pub width: usize,
+    pub height: usize,
+    pub cell_size: usize,
 }
 
-impl Default for SongInfo {
+impl Default for GridInfo {
     fn default() -> Self {
         Self {
-            is_playing: false,
-            bpm: 120,
-            divisor: 4,
-            is_looping: false,
-            loop_len: 16,
-            step_num: 0,
+            width: 24,
+            height: 24,
+            cell_size: 16,
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum Message {
-    None,
-    MapMessage(map::Message),
-    MaskMessage(mask::Message),
-    NewMap(CellMap),
-    HitCount(u8),
-    Tick(Instant),
-    RandomizeMap,
-    RandomizeMask,
-    Reset,
-    ClearMap,
-    ClearMask,
-    Save,
-    TogglePlayback,
-    SpeedChanged(usize),
-    NewDivision(usize),
-    ToggleLoop,
-    LoopLength(usize),
-    ProbChanged(f32),
-    RandChanged(f32),
-    NewVMin(u8),
-    NewVMax(u8),
-    ChannelChange(u8),
-    Scale(Scale),
-    NewOctave(u8),
-    OctaveRange(u8),
-    NewNote(Root),
-    Voices(u8),
-    Quit,
-}
+impl GridInfo {
+    pub const MIN_CELLS: usize = 8;
+    pub const MAX_CELLS: usize = 64;
+    pub const MIN_SIZE: usize = 4;
+    pub const MAX_SIZE: usize = 32;
 
-impl CellSeq {
-    fn control_message(&self) -> ControlMessage {
-        ControlMessage {
-            randomness: self.map.randomness(),
-            info: self.info.clone(),
-            song: self.song.clone(),
+    // the same grid with every setting brought within its limits
+    pub fn clamped(self) -> Self {
+        Self {
+            width: self.width.clamp(Self::MIN_CELLS, Self::MAX_CELLS),
+            height: self.height.clamp(Self::MIN_CELLS, Self::MAX_CELLS),
+            cell_size: self.cell_size.clamp(Self::MIN_SIZE, Self::MAX_SIZE),
         }
     }
-}
 
-impl Application for CellSeq {
-    type Message = Message;
-    type Theme = Theme;
-    type Executor = executor::Default;
-    type Flags = MidiLink;
-
-    fn new(flags: Self::Flags) -> (Self, Command<Message>) {
-        (
-            Self {
-                midi: flags,
-                ..Self::default()
-            },
-            Command::none(),
-        )
+    fn contains(&self, cell: Cell) -> bool {
+        (0..self.height as isize).contains(&cell.i) && (0..self.width as isize).contains(&cell.j)
     }
 
-    fn title(&self) -> String {
-        String::from("cellseq")
-    }
-
-    fn update(&mut self, message: Message) -> Command<Message> {
-        match message {
-            Message::None => {}
-            Message::MapMessage(message) => self.map.update(message),
-            Message::MaskMessage(message) => self.mask.update(message),
-            Message::HitCount(x) => self.midi.update(x, &self.info),
-            Message::NewMap(m) => {
-                self.map.update(map::Message::Ticked(m.clone()));
-                let hits = self.mask.tick(m);
-                return Command::perform(async move { hits }, Message::HitCount);
-            }
-            Message::Tick(_) => {
-                let map = if self.song.is_looping && self.song.step_num >= self.song.loop_len {
-                    self.song.step_num = 1;
-                    self.map.reset_loop()
-                } else {
-                    self.song.step_num += 1;
-                    self.map.tick()
-                };
-
-                let channel = self.midi.channel_handle();
-                let bytes = self.midi.tick();
-
-                let new_map = map.clone();
-                let hits = self.mask.tick(map);
-
-                let midi = tokio::spawn(async move {
-                    for byte in bytes {
-                        channel.send(byte).await.unwrap()
-                    }
-                });
-
-                let mut commands = Vec::new();
-                commands.push(Command::perform(midi, |_| Message::None));
-                commands.push(Command::perform(async move { new_map }, Message::NewMap));
-                commands.push(Command::perform(async move { hits }, Message::HitCount));
-                commands.push(Command::perform(async move {}, |_| {
-                    Message::MaskMessage(mask::Message::Ticked)
-                }));
-
-                return Command::batch(commands);
-            }
-            Message::TogglePlayback => {
-                if self.song.is_playing {
-                    let bytes = self.midi.all_off(self.info.channel);
-                    let channel = self.midi.channel_handle();
-                    for byte in bytes {
-                        channel.try_send(byte).unwrap();
-                    }
-                }
-                self.song.is_playing = !self.song.is_playing;
-            }
-            Message::ToggleLoop => {
-                self.song.is_looping = !self.song.is_looping;
-                if self.song.is_looping {
-                    self.map.set_loop();
-                    self.song.step_num = 1;
-                }
-            }
-            Message::RandChanged(r) => {
-                self.map.set_randomness(r);
-                self.mask.set_randomness(r);
-            }
-            Message::RandomizeMap => self.map.randomize(),
-            Message::RandomizeMask => self.mask.randomize(),
-            Message::ClearMap => self.map.clear(),
-            Message::ClearMask => self.mask.clear(),
-            Message::Reset => self.map.reset(),
-            Message::Save => self.map.save(),
-            Message::SpeedChanged(b) => self.song.bpm = b,
-            Message::NewDivision(d) => self.song.divisor = d,
-            Message::LoopLength(l) => self.song.loop_len = l,
-            Message::ProbChanged(p) => self.info.probability = p,
-            Message::NewVMin(v) => self.info.velocity.set_min(v),
-            Message::NewVMax(v) => self.info.velocity.set_max(v),
-            Message::ChannelChange(c) => self.info.channel = c,
-            Message::Scale(s) => self.info.scale = s,
-            Message::NewOctave(o) => self.info.octave.center = o,
-            Message::OctaveRange(r) => self.info.octave.range = r,
-            Message::NewNote(r) => self.info.root = r,
-            Message::Voices(v) => self.info.voices = v,
-            Message::Quit => return window::close(),
+    fn wrap(&self, cell: Cell) -> Cell {
+        Cell {
+            i: cell.i.rem_euclid(self.height as isize),
+            j: cell.j.rem_euclid(self.width as isize),
         }
-
-        Command::none()
     }
 
-    fn subscription(&self) -> Subscription<Message> {
-        if self.song.is_playing {
-            time::every(Duration::from_millis(
-                240000 / (self.song.bpm * self.song.divisor) as u64,
-            ))
-            .map(Message::Tick)
-        } else {
-            Subscription::none()
-        }
+    fn cells(&self) -> impl Iterator<Item = Cell> {
+        (0..self.height as isize)
+            .cartesian_product(0..self.width as isize)
+            .map(|(i, j)| Cell { i, j })
     }
+}
 
-    fn view(&self) -> Element<Message> {
-        let top = top_controls(self.song.is_playing);
-
-        let probability_slider = container(
-            column![
-                text(format!("{}", (self.info.probability * 100.0).round())),
-                vertical_slider(0.0..=100.0, self.info.probability * 100.0, |x| {
-                    Message::ProbChanged(x / 100.0)
-                }),
-                text("note density")
-            ]
-            .height(Length::Fixed(350.0))
-            .align_items(Alignment::Center),
-        )
-        .align_y(Vertical::Top);
-
-        let map = container(
-            row![
-                self.map.view().map(Message::MapMessage),
-                probability_slider,
-                self.mask.view().map(Message::MaskMessage)
-            ]
-            .padding(10)
-            .spacing(20),
-        )
-        .align_x(Horizontal::Center);
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SongInfo {
+    #[serde(skip)]
+    pub is_playing: bool,
+    pub bpm: usize,
+    pub divisor: usize,
+    pub is_looping: bool,
+    pub loop_len: usize,
+    #[serde(skip)]
+    pub step_num: usize,
+    pub clock_out: bool,
+    pub external_sync: bool,
+    // how far the external clock has gone towards the next step, in pulses
+    // times the divisor so that steps which aren't a whole number of pulses
+    // long still land in time on average
+    #[serde(skip)]
+    pub clock_phase: usize,
+}
 
-        let bottom = bottom_controls(self.control_message());
+impl SongInfo {
+    // as far as learned controls go, which also keeps the timing sums in range
+    pub const MAX_BPM: usize = 300;
+    pub const MAX_DIVISOR: usize = 64;
 
-        container(
-            column![top, vertical_space(40), map, bottom]
-                .width(Length::Fill)
-                .align_items(Alignment::Center),
-        )
-        .align_x(Horizontal::Center)
-        .width(Length::Fill)
-        .height(Length::Fill)
-        .into()
+    // the same song with the tempo and division brought within their limits
+    pub fn clamped(self) -> Self {
+        Self {
+            bpm: self.bpm.clamp(1, Self::MAX_BPM),
+            divisor: self.divisor.clamp(1, Self::MAX_DIVISOR),
+            ..self
+        }
     }
+}
 
-    fn theme(&self) -> Theme {
-        Theme::custom(iced::theme::Palette {
-            background: Color::from_rgb8(0x15, 0x15, 0x15),
-            text: Color::from_rgb8(0xD7, 0xD0, 0xC7),
-            primary: Color::from_rgb8(0x9B, 0x64, 0xFB),
-            success: Color::from_rgb8(0x42, 0x71, 0x7B),
-            danger: Color::from_rgb8(0xD2, 0x3D, 0x3D),
-        })
+impl Default for SongInfo {
+    fn default() -> Self {
+        Self {
+            is_playing: false,
+            bpm: 120,
+            divisor: 4,
+            is_looping: false,
+            loop_len: 16,
+            step_num: 0,
+            clock_out: true,
+            external_sync: false,
+            clock_phase: 0,
+        }
     }
 }
@@ -1,27 +1,151 @@
-use std::{io::Write, thread::JoinHandle};
+use std::path::Path;
+#[cfg(feature = "gui")]
+use std::{
+    io::Read,
+    sync::{Arc, Mutex},
+};
 
+#[cfg(feature = "gui")]
 use alsa::{rawmidi::Rawmidi, Direction};
 use cellseq::*;
 
+#[cfg(feature = "gui")]
 use iced::{window, Application, Settings};
 
 use eyre::{eyre, Result};
+#[cfg(feature = "gui")]
 use tokio::sync::mpsc::channel;
 
+#[derive(Default)]
+struct Args {
+    session: Option<String>,
+    backend: Backend,
+    port: Option<Port>,
+    list_ports: bool,
+    // renders to a file instead of opening the window
+    render: bool,
+    steps: usize,
+    out: Option<String>,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut parsed = Args {
+            steps: 64,
+            ..Args::default()
+        };
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--session" => {
+                    parsed.session = Some(args.next().ok_or(eyre!("--session needs a file"))?)
+                }
+                "--backend" => {
+                    parsed.backend = args
+                        .next()
+                        .ok_or(eyre!("--backend needs one of rawmidi, seq or jack"))?
+                        .parse()?
+                }
+                "--port" => {
+                    parsed.port = Some(Port::Named(
+                        args.next().ok_or(eyre!("--port needs a port name"))?,
+                    ))
+                }
+                "--list-ports" => parsed.list_ports = true,
+                "render" => parsed.render = true,
+                "--steps" => {
+                    parsed.steps = args
+                        .next()
+                        .ok_or(eyre!("--steps needs a number of steps"))?
+                        .parse()?
+                }
+                "--out" => parsed.out = Some(args.next().ok_or(eyre!("--out needs a file"))?),
+                _ => return Err(eyre!("unknown argument: {arg}")),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
 pub fn main() -> Result<()> {
-    let (midi_snd, mut midi_rcv) = channel::<u8>(256);
-    let midi = MidiLink::new(midi_snd);
+    let args = Args::parse()?;
+
+    if args.list_ports {
+        for port in args.backend.ports()? {
+            println!("{port}");
+        }
+        return Ok(());
+    }
+
+    if args.render {
+        return render(&args);
+    }
+
+    run(args)
+}
 
-    let midi_sink = Rawmidi::new("virtual", Direction::Playback, false)?;
+// opens the window, with the sequencer running behind it
+#[cfg(feature = "gui")]
+fn run(args: Args) -> Result<()> {
+    // a session file that doesn't exist yet is created on the first save
+    let session = match &args.session {
+        Some(path) if Path::new(path).exists() => Some(Session::open(Path::new(path))?),
+        _ => None,
+    };
 
-    let midi_loop: JoinHandle<Result<()>> = std::thread::spawn(move || {
-        let mut midi_io = midi_sink.io();
-        while let Some(byte) = midi_rcv.blocking_recv() {
-            midi_io.write_all(&[byte])?;
+    let (midi_snd, mut midi_rcv) = channel::<Timed>(256);
+    let sequencer = Arc::new(Mutex::new(Sequencer::new(MidiLink::new(midi_snd))));
+    let (scheduler, snapshots) = Scheduler::spawn(sequencer.clone());
+
+    // without a device to send to the sequencer still runs, it just stays quiet
+    let output = Output::new(args.backend);
+    let mut port = args.port.unwrap_or(args.backend.default_port());
+    if let Err(e) = output.open(&port) {
+        eprintln!("midi output unavailable: {e}");
+        port = Port::Off;
+    }
+
+    let midi_sink = output.clone();
+    let midi_loop = std::thread::spawn(move || {
+        while let Some(timed) = midi_rcv.blocking_recv() {
+            if let Err(e) = midi_sink.send(timed) {
+                eprintln!("{e}");
+            }
         }
-        Ok(())
     });
 
+    // incoming clock goes straight to the scheduler, notes and controllers to the gui
+    let (input_snd, input_rcv) = channel::<MidiMessage>(64);
+    match Rawmidi::new("virtual", Direction::Capture, false) {
+        Ok(midi_source) => {
+            let events = scheduler.sender();
+            std::thread::spawn(move || {
+                let mut midi_io = midi_source.io();
+                let mut parser = MidiParser::default();
+                let mut byte = [0; 1];
+                while midi_io.read_exact(&mut byte).is_ok() {
+                    let sent = match parser.parse(byte[0]) {
+                        Ok(Some(message)) if message.is_realtime() => {
+                            events.send(Event::Clock(message)).is_ok()
+                        }
+                        Ok(Some(message)) => input_snd.blocking_send(message).is_ok(),
+                        Ok(None) => true,
+                        Err(e) => {
+                            eprintln!("{e}");
+                            true
+                        }
+                    };
+                    if !sent {
+                        break;
+                    }
+                }
+            });
+        }
+        Err(e) => eprintln!("midi input unavailable: {e}"),
+    }
+
     // running the graphics window
     CellSeq::run(Settings {
         antialiasing: true,
@@ -29,11 +153,49 @@ pub fn main() -> Result<()> {
             position: window::Position::Centered,
             ..window::Settings::default()
         },
-        flags: midi,
-        ..Settings::default()
+        ..Settings::with_flags(Flags {
+            sequencer,
+            scheduler,
+            snapshots,
+            inputs: Inputs::new(input_rcv),
+            output,
+            port,
+            session_path: args.session,
+            session,
+        })
     })?;
 
-    midi_loop.join().map_err(|_| eyre!("join failure"))??;
+    midi_loop.join().map_err(|_| eyre!("join failure"))?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "gui"))]
+fn run(_args: Args) -> Result<()> {
+    Err(eyre!(
+        "built without the window, only render and --list-ports are available"
+    ))
+}
+
+// plays a session through as fast as it can, straight into a midi file
+fn render(args: &Args) -> Result<()> {
+    let session = args
+        .session
+        .as_ref()
+        .ok_or(eyre!("render needs a --session to play"))?;
+    let out = args
+        .out
+        .as_ref()
+        .ok_or(eyre!("render needs an --out file"))?;
+
+    let mut sequencer = Sequencer::default();
+    sequencer.load_session(Session::open(Path::new(session))?);
+    sequencer.start_recording(out.into());
+    sequencer.render(args.steps);
+
+    if let Some(recording) = sequencer.stop_recording() {
+        recording.save()?;
+    }
 
     Ok(())
 }
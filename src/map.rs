@@ -1,26 +1,19 @@
-use iced::{
-    mouse::{self, Button::Left, Event::ButtonPressed},
-    widget::canvas::{
-        event::{self, Event},
-        Cache, Canvas, Cursor, Geometry, Path, Program,
-    },
-    {Color, Element, Length, Point, Rectangle, Size, Theme},
-};
-
 use super::*;
 
-use itertools::Itertools;
-use rand::random;
+use rand::Rng;
 use rustc_hash::FxHashMap;
-use std::fmt::Debug;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Display};
 
 #[derive(Debug)]
 pub struct Map {
     seed: CellMap,
     loop_point: CellMap,
     cells: CellMap,
-    life_cache: Cache,
     randomness: f32,
+    rule: Rule,
+    topology: Topology,
+    grid: GridInfo,
 }
 
 impl Default for Map {
@@ -29,12 +22,48 @@ impl Default for Map {
             seed: CellMap::default(),
             cells: CellMap::default(),
             loop_point: CellMap::default(),
-            life_cache: Cache::default(),
             randomness: 0.5,
+            rule: Rule::default(),
+            topology: Topology::default(),
+            grid: GridInfo::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topology {
+    #[default]
+    Infinite,
+    Torus,
+    Walls,
+}
+
+impl Topology {
+    pub const ALL: [Topology; 3] = [Topology::Infinite, Topology::Torus, Topology::Walls];
+
+    // where a cell ends up on the board, if it is still on it at all
+    pub fn place(&self, cell: Cell, grid: GridInfo) -> Option<Cell> {
+        match self {
+            Topology::Infinite => Some(cell),
+            Topology::Torus => Some(grid.wrap(cell)),
+            Topology::Walls => grid.contains(cell).then_some(cell),
         }
     }
 }
 
+impl Display for Topology {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Topology::Infinite => "infinite",
+            Topology::Torus => "torus",
+            Topology::Walls => "walls",
+        };
+
+        write!(f, "{str}")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     Populate(Cell),
@@ -52,13 +81,19 @@ impl Map {
     }
 
     pub fn tick(&self) -> CellMap {
-        let mut life = self.cells.clone();
+        let cells: CellMap = self
+            .cells
+            .iter()
+            .filter_map(|cell| self.topology.place(*cell, self.grid))
+            .collect();
+
+        let mut life = cells.clone();
         let mut counts = FxHashMap::default();
 
-        for cell in &life {
+        for cell in &cells {
             counts.entry(*cell).or_insert(0);
 
-            for neighbor in Cell::neighbors(*cell) {
+            for neighbor in Cell::neighbors(*cell, self.topology, self.grid) {
                 let amount = counts.entry(neighbor).or_insert(0);
 
                 *amount += 1;
@@ -66,14 +101,12 @@ impl Map {
         }
 
         for (cell, amount) in counts.iter() {
-            match amount {
-                2 => {}
-                3 => {
-                    life.insert(*cell);
-                }
-                _ => {
+            if cells.contains(cell) {
+                if !self.rule.survives(*amount) {
                     life.remove(cell);
                 }
+            } else if self.rule.born(*amount) {
+                life.insert(*cell);
             }
         }
 
@@ -84,49 +117,51 @@ impl Map {
         match message {
             Message::Populate(cell) => {
                 self.cells.insert(cell);
-                self.life_cache.clear();
             }
             Message::Unpopulate(cell) => {
                 self.cells.remove(&cell);
-                self.life_cache.clear();
-            }
-            Message::Ticked(life) => {
-                self.cells = life;
-                self.life_cache.clear();
             }
+            Message::Ticked(life) => self.cells = life,
         }
     }
 
-    pub fn view(&self) -> Element<Message> {
-        Canvas::new(self)
-            .width(Length::Fixed(Cell::SIZE as f32 * 24.0))
-            .height(Length::Fixed(Cell::SIZE as f32 * 24.0))
-            .into()
-    }
-
     pub fn clear(&mut self) {
         self.cells.clear();
-        self.life_cache.clear();
     }
 
     pub fn reset(&mut self) {
         self.cells = self.seed.clone();
-        self.life_cache.clear();
     }
 
     pub fn save(&mut self) {
         self.seed = self.cells.clone();
     }
 
-    pub fn randomize(&mut self) {
+    pub fn randomize(&mut self, rng: &mut impl Rng) {
         self.cells.clear();
-        for (i, j) in (-32..=32).cartesian_product(-32..=32) {
-            if random::<f32>() < self.randomness {
-                self.cells.insert(Cell { i, j });
+        for cell in self.grid.cells() {
+            if rng.gen::<f32>() < self.randomness {
+                self.cells.insert(cell);
             }
         }
         self.seed = self.cells.clone();
-        self.life_cache.clear();
+    }
+
+    pub fn cells(&self) -> &CellMap {
+        &self.cells
+    }
+
+    pub fn seed(&self) -> &CellMap {
+        &self.seed
+    }
+
+    pub fn add_pattern(&mut self, pattern: CellMap) {
+        self.cells.extend(pattern);
+    }
+
+    pub fn restore(&mut self, seed: CellMap, cells: CellMap) {
+        self.seed = seed;
+        self.cells = cells;
     }
 
     pub fn randomness(&self) -> f32 {
@@ -136,69 +171,121 @@ impl Map {
     pub fn set_randomness(&mut self, value: f32) {
         self.randomness = value;
     }
+
+    pub fn rule(&self) -> Rule {
+        self.rule
+    }
+
+    pub fn set_rule(&mut self, rule: Rule) {
+        self.rule = rule;
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+
+    pub fn set_topology(&mut self, topology: Topology) {
+        self.topology = topology;
+    }
+
+    pub fn set_grid(&mut self, grid: GridInfo) {
+        self.grid = grid;
+    }
 }
 
-impl Program<Message> for Map {
-    type State = bool;
-
-    fn update(
-        &self,
-        _interaction: &mut bool,
-        event: Event,
-        bounds: Rectangle,
-        cursor: Cursor,
-    ) -> (event::Status, Option<Message>) {
-        if let Some(position) = cursor.position_in(&bounds) {
-            if let Event::Mouse(ButtonPressed(Left)) = event {
-                let cell = Cell::at(position);
-                return (
-                    event::Status::Captured,
-                    if self.cells.contains(&cell) {
-                        Some(Message::Unpopulate(cell))
-                    } else {
-                        Some(Message::Populate(cell))
-                    },
-                );
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(list: &[(isize, isize)]) -> CellMap {
+        list.iter().map(|(i, j)| Cell::new(*i, *j)).collect()
+    }
+
+    fn shifted(cells: &CellMap, i: isize, j: isize) -> CellMap {
+        cells
+            .iter()
+            .map(|cell| Cell::new(cell.i + i, cell.j + j))
+            .collect()
+    }
+
+    fn map(list: &[(isize, isize)]) -> Map {
+        let mut map = Map::default();
+        for cell in cells(list) {
+            map.update(Message::Populate(cell));
+        }
+        map
+    }
+
+    fn run(map: &mut Map, generations: usize) {
+        for _ in 0..generations {
+            let life = map.tick();
+            map.update(Message::Ticked(life));
+        }
+    }
+
+    const GLIDER: [(isize, isize); 5] = [(0, 1), (1, 2), (2, 0), (2, 1), (2, 2)];
+
+    #[test]
+    fn block_stays_still() {
+        let block = [(5, 5), (5, 6), (6, 5), (6, 6)];
+        let mut map = map(&block);
+        run(&mut map, 10);
+        assert_eq!(map.cells(), &cells(&block));
+    }
+
+    #[test]
+    fn blinker_oscillates() {
+        let vertical = [(4, 5), (5, 5), (6, 5)];
+        let horizontal = [(5, 4), (5, 5), (5, 6)];
+        let mut map = map(&vertical);
+
+        run(&mut map, 1);
+        assert_eq!(map.cells(), &cells(&horizontal));
+        run(&mut map, 1);
+        assert_eq!(map.cells(), &cells(&vertical));
+    }
+
+    #[test]
+    fn glider_moves_diagonally() {
+        let start = shifted(&cells(&GLIDER), 4, 4);
+        let mut map = map(&[]);
+        map.add_pattern(start.clone());
+
+        for moved in 1..=4 {
+            run(&mut map, 4);
+            assert_eq!(map.cells(), &shifted(&start, moved, moved));
         }
+    }
 
-        (event::Status::Ignored, None)
-    }
-
-    fn draw(
-        &self,
-        _interaction: &bool,
-        _theme: &Theme,
-        bounds: Rectangle,
-        _cursor: Cursor,
-    ) -> Vec<Geometry> {
-        vec![self.life_cache.draw(bounds.size(), |frame| {
-            let background = Path::rectangle(Point::ORIGIN, frame.size());
-            frame.fill(&background, Color::from_rgb8(0x30, 0x30, 0x30));
-
-            frame.with_save(|frame| {
-                frame.scale(Cell::SIZE as f32);
-
-                (0..24)
-                    .cartesian_product(0..24)
-                    .filter(|x| self.cells.contains(&Cell { i: x.1, j: x.0 }))
-                    .for_each(|x| {
-                        frame.fill_rectangle(
-                            Point::new(x.0 as f32, x.1 as f32),
-                            Size::UNIT,
-                            Color::from_rgb8(0xD7, 0xD0, 0xC7),
-                        );
-                    })
-            });
-        })]
-    }
-
-    fn mouse_interaction(
-        &self,
-        _interaction: &bool,
-        _bounds: Rectangle,
-        _cursor: Cursor,
-    ) -> mouse::Interaction {
-        mouse::Interaction::default()
+    #[test]
+    fn glider_wraps_around_torus() {
+        let mut map = map(&GLIDER);
+        map.set_topology(Topology::Torus);
+        map.set_grid(GridInfo {
+            width: 8,
+            height: 8,
+            ..GridInfo::default()
+        });
+
+        // a cell every four generations takes it once round an eight cell board
+        run(&mut map, 32);
+        assert_eq!(map.cells(), &cells(&GLIDER));
+    }
+
+    #[test]
+    fn walls_keep_cells_on_the_board() {
+        let grid = GridInfo {
+            width: 8,
+            height: 8,
+            ..GridInfo::default()
+        };
+        let mut map = map(&GLIDER);
+        map.set_topology(Topology::Walls);
+        map.set_grid(grid);
+
+        for _ in 0..40 {
+            run(&mut map, 1);
+            assert!(map.cells().iter().all(|cell| grid.contains(*cell)));
+        }
     }
 }
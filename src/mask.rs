@@ -1,40 +1,29 @@
-use iced::{
-    mouse::Interaction,
-    mouse::{Button::Left, Event::ButtonPressed},
-    widget::canvas::{
-        event::{self, Event},
-        Cache, Canvas, Cursor, Geometry, Path, Program,
-    },
-    {Color, Element, Length, Point, Rectangle, Size, Theme},
-};
-use rand::random;
-
-use crate::{Cell, CellMap};
-use itertools::Itertools;
+use rand::Rng;
+
+use crate::{Cell, CellMap, GridInfo};
 use rustc_hash::FxHashSet;
 
 #[derive(Debug, Clone)]
 pub enum Message {
     Check(Cell),
     Uncheck(Cell),
-    Ticked,
 }
 
 #[derive(Debug)]
 pub struct Mask {
     cells: FxHashSet<Cell>,
     hits: FxHashSet<Cell>,
-    mask_cache: Cache,
     randomness: f32,
+    grid: GridInfo,
 }
 
 impl Default for Mask {
     fn default() -> Self {
         Self {
             cells: FxHashSet::default(),
-            mask_cache: Cache::default(),
             randomness: 0.5,
             hits: FxHashSet::default(),
+            grid: GridInfo::default(),
         }
     }
 }
@@ -44,118 +33,107 @@ impl Mask {
         match message {
             Message::Check(cell) => {
                 self.cells.insert(cell);
-                self.mask_cache.clear()
             }
             Message::Uncheck(cell) => {
                 self.cells.remove(&cell);
-                self.mask_cache.clear();
             }
-            Message::Ticked => self.mask_cache.clear(),
         }
     }
 
-    pub fn view(&self) -> Element<Message> {
-        Canvas::new(self)
-            .width(Length::Fixed(Cell::SIZE as f32 * 24.0))
-            .height(Length::Fixed(Cell::SIZE as f32 * 24.0))
-            .into()
-    }
-
-    pub fn tick(&mut self, life: CellMap) -> u8 {
+    // cells left off the board by a smaller grid or an imported pattern are kept
+    // for when they come back into view, but don't play until then
+    pub fn tick(&mut self, life: &CellMap) -> CellMap {
         self.hits.clear();
         for cell in self.cells.iter() {
-            if life.contains(cell) {
+            if self.grid.contains(*cell) && life.contains(cell) {
                 self.hits.insert(*cell);
             }
         }
 
-        self.hits.len().try_into().unwrap_or(127)
+        self.hits.clone()
     }
 
-    pub fn randomize(&mut self) {
+    pub fn randomize(&mut self, rng: &mut impl Rng) {
         self.cells.clear();
-        for (i, j) in (-32..=32).cartesian_product(-32..=32) {
-            if random::<f32>() < self.randomness {
-                self.cells.insert(Cell { i, j });
+        for cell in self.grid.cells() {
+            if rng.gen::<f32>() < self.randomness {
+                self.cells.insert(cell);
             }
         }
-        self.mask_cache.clear();
+    }
+
+    pub fn cells(&self) -> &CellMap {
+        &self.cells
+    }
+
+    pub fn hits(&self) -> &CellMap {
+        &self.hits
+    }
+
+    pub fn add_pattern(&mut self, pattern: CellMap) {
+        self.cells.extend(pattern);
+    }
+
+    pub fn set_cells(&mut self, cells: CellMap) {
+        self.cells = cells;
     }
 
     pub fn set_randomness(&mut self, value: f32) {
         self.randomness = value;
     }
 
+    pub fn set_grid(&mut self, grid: GridInfo) {
+        self.grid = grid;
+    }
+
     pub fn clear(&mut self) {
         self.cells.clear();
-        self.mask_cache.clear();
     }
 }
 
-impl Program<Message> for Mask {
-    type State = bool;
-    fn draw(
-        &self,
-        _state: &Self::State,
-        _theme: &Theme,
-        bounds: Rectangle,
-        _cursor: Cursor,
-    ) -> Vec<Geometry> {
-        vec![self.mask_cache.draw(bounds.size(), |frame| {
-            let background = Path::rectangle(Point::ORIGIN, frame.size());
-            frame.fill(&background, Color::from_rgb8(0x30, 0x30, 0x30));
-
-            frame.with_save(|frame| {
-                frame.scale(Cell::SIZE as f32);
-
-                (0..24)
-                    .cartesian_product(0..24)
-                    .filter(|x| self.cells.contains(&Cell { i: x.1, j: x.0 }))
-                    .for_each(|x| {
-                        frame.fill_rectangle(
-                            Point::new(x.0 as f32, x.1 as f32),
-                            Size::UNIT,
-                            if self.hits.contains(&Cell { i: x.1, j: x.0 }) {
-                                Color::from_rgb8(0x42, 0x71, 0x7B)
-                            } else {
-                                Color::from_rgb8(0xD7, 0xD0, 0xC7)
-                            },
-                        );
-                    })
-            });
-        })]
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+
+    fn cells() -> impl Strategy<Value = CellMap> {
+        vec((0..24isize, 0..24isize), 0..64)
+            .prop_map(|list| list.into_iter().map(|(i, j)| Cell::new(i, j)).collect())
     }
 
-    fn update(
-        &self,
-        _state: &mut Self::State,
-        event: Event,
-        bounds: Rectangle,
-        cursor: Cursor,
-    ) -> (event::Status, Option<Message>) {
-        if let Some(position) = cursor.position_in(&bounds) {
-            if let Event::Mouse(ButtonPressed(Left)) = event {
-                let cell = Cell::at(position);
-                return (
-                    event::Status::Captured,
-                    if self.cells.contains(&cell) {
-                        Some(Message::Uncheck(cell))
-                    } else {
-                        Some(Message::Check(cell))
-                    },
-                );
-            }
-        }
+    #[test]
+    fn hits_replace_the_last_step() {
+        let mut mask = Mask::default();
+        mask.set_cells([Cell::new(0, 0), Cell::new(1, 1)].into_iter().collect());
 
-        (event::Status::Ignored, None)
+        let hits = mask.tick(&[Cell::new(0, 0)].into_iter().collect());
+        assert_eq!(hits.len(), 1);
+
+        let hits = mask.tick(&[Cell::new(1, 1), Cell::new(2, 2)].into_iter().collect());
+        assert_eq!(hits, [Cell::new(1, 1)].into_iter().collect());
+        assert_eq!(mask.hits(), &hits);
+    }
+
+    #[test]
+    fn cells_off_the_board_stay_quiet() {
+        let mut mask = Mask::default();
+        let outside = [Cell::new(-1, 0), Cell::new(0, 24), Cell::new(30, 30)];
+        mask.set_cells(outside.into_iter().collect());
+
+        assert!(mask.tick(&outside.into_iter().collect()).is_empty());
     }
 
-    fn mouse_interaction(
-        &self,
-        _state: &Self::State,
-        _bounds: Rectangle,
-        _cursor: Cursor,
-    ) -> Interaction {
-        Interaction::default()
+    proptest! {
+        #[test]
+        fn hits_are_cells_on_both(checked in cells(), life in cells()) {
+            let mut mask = Mask::default();
+            mask.set_cells(checked.clone());
+
+            let hits = mask.tick(&life);
+            let expected: CellMap = checked.intersection(&life).copied().collect();
+            prop_assert_eq!(hits.len(), expected.len());
+            prop_assert_eq!(hits, expected);
+        }
     }
 }
@@ -1,23 +1,38 @@
-use std::{collections::HashSet, fmt::Display};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    time::Instant,
+};
 
 use eyre::Result;
-use rand::random;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::sync::mpsc::Sender;
 
-use crate::music::{
-    generate_note, generate_velocity, Accidental, Octave, Root, RootNote, Scale, Velocity,
+use crate::{
+    music::{
+        generate_gate, generate_velocity, map_note, Accidental, Drums, Gate, Octave, PitchMode,
+        Root, RootNote, Scale, Velocity,
+    },
+    CellMap, GridInfo,
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MidiInfo {
     pub channel: u8,
     pub velocity: Velocity,
+    pub gate: Gate,
     pub octave: Octave,
     pub scale: Scale,
+    pub pitch_mode: PitchMode,
+    pub drums: Drums,
     pub root: Root,
     pub voices: u8,
     pub probability: f32,
+    // sent when a session is opened, to recall the patch on the synth
+    pub program: Option<u8>,
 }
 
 impl Default for MidiInfo {
@@ -25,23 +40,49 @@ impl Default for MidiInfo {
         Self {
             channel: 0,
             velocity: Velocity::new(64, 127),
+            gate: Gate::default(),
             octave: Octave::default(),
             scale: Scale::Chromatic,
+            pitch_mode: PitchMode::Random,
+            drums: Drums::default(),
             root: Root {
                 note: RootNote::C,
                 accidental: Accidental::Natural,
             },
             voices: 6,
             probability: 0.5,
+            program: None,
+        }
+    }
+}
+
+impl MidiInfo {
+    // the same settings with everything that goes out in a message kept to what
+    // fits in one
+    pub fn clamped(self) -> Self {
+        Self {
+            channel: self.channel.min(15),
+            velocity: self.velocity.clamped(),
+            drums: self.drums.clamped(),
+            program: self.program.map(|program| program.min(127)),
+            ..self
         }
     }
 }
 
+// a message along with when it is meant to go out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timed {
+    pub message: MidiMessage,
+    pub time: Instant,
+}
+
 #[derive(Clone, Debug)]
 pub struct MidiLink {
     buffer: Vec<MidiMessage>,
-    channel: Sender<u8>,
-    notes_on: HashSet<u8>,
+    channel: Sender<Timed>,
+    // steps left on the gate of every sounding (channel, note)
+    notes_on: HashMap<(u8, u8), f32>,
 }
 
 impl Default for MidiLink {
@@ -50,95 +91,143 @@ impl Default for MidiLink {
         Self {
             channel: send,
             buffer: Vec::default(),
-            notes_on: HashSet::default(),
+            notes_on: HashMap::default(),
         }
     }
 }
 
 impl MidiLink {
-    pub fn new(channel: Sender<u8>) -> Self {
+    pub fn new(channel: Sender<Timed>) -> Self {
         Self {
             channel,
             ..Self::default()
         }
     }
 
-    pub fn channel_handle(&self) -> Sender<u8> {
+    pub fn channel_handle(&self) -> Sender<Timed> {
         self.channel.clone()
     }
 
-    pub fn update(&mut self, hits: u8, info: &MidiInfo) {
+    pub fn update(
+        &mut self,
+        hits: &CellMap,
+        info: &MidiInfo,
+        grid: GridInfo,
+        held: &[u8],
+        rng: &mut impl Rng,
+    ) {
         let mut count = 0;
+        let mut struck = HashSet::new();
 
-        for _ in 0..hits {
+        for cell in hits.iter() {
             if count > info.voices {
                 break;
-            } else if random::<f32>() > info.probability {
+            } else if rng.gen::<f32>() > info.probability {
                 count += 1;
                 continue;
             } else {
                 count += 1;
-                let note = generate_note(info);
+                let Some(note) = map_note(info, *cell, grid, held, rng) else {
+                    continue;
+                };
+                if !struck.insert(note) {
+                    continue;
+                }
 
-                if self.notes_on.contains(&note) {
-                    self.notes_on.remove(&note);
-                    self.buffer.push(MidiMessage::Off {
-                        note,
-                        velocity: generate_velocity(info.velocity),
-                        channel: info.channel,
-                    });
+                // voices are counted per channel, so every layer gets its own polyphony
+                let sounding = self
+                    .notes_on
+                    .iter()
+                    .filter(|((c, _), _)| *c == info.channel);
+
+                // a note that is still sounding is cut and struck again with a fresh gate
+                let stolen = if self.notes_on.contains_key(&(info.channel, note)) {
+                    Some((info.channel, note))
+                } else if sounding.clone().count() > info.voices.into() {
+                    sounding
+                        .min_by(|a, b| a.1.total_cmp(b.1).then(a.0.cmp(b.0)))
+                        .map(|(key, _)| *key)
                 } else {
-                    if self.notes_on.len() > info.voices.into() {
-                        if let Some(elem) = self.notes_on.iter().next().cloned() {
-                            self.notes_on.remove(&elem);
-                            self.buffer.push(MidiMessage::Off {
-                                note: elem,
-                                velocity: generate_velocity(info.velocity),
-                                channel: info.channel,
-                            });
-                        }
-                    }
-                    self.notes_on.insert(note);
-                    self.buffer.push(MidiMessage::On {
+                    None
+                };
+
+                if let Some((channel, note)) = stolen {
+                    self.notes_on.remove(&(channel, note));
+                    self.buffer.push(MidiMessage::Off {
                         note,
-                        velocity: generate_velocity(info.velocity),
-                        channel: info.channel,
+                        velocity: generate_velocity(info.velocity, rng),
+                        channel,
                     });
                 }
+
+                self.notes_on
+                    .insert((info.channel, note), generate_gate(info.gate, rng));
+                self.buffer.push(MidiMessage::On {
+                    note,
+                    velocity: generate_velocity(info.velocity, rng),
+                    channel: info.channel,
+                });
             }
         }
     }
 
-    pub fn tick(&mut self) -> Vec<u8> {
-        let vec: Vec<u8> = self
-            .buffer
-            .iter()
-            .flat_map(|m| m.as_bytes().unwrap())
-            .flatten()
-            .collect();
+    // counts every gate down by a (possibly fractional) number of steps,
+    // turning off the notes whose gates run out
+    pub fn release(&mut self, steps: f32) {
+        let mut expired: Vec<(u8, u8)> = Vec::new();
+        for (key, gate) in self.notes_on.iter_mut() {
+            *gate -= steps;
+            // leaves room for the rounding of gates counted down in pulses
+            if *gate <= 1e-3 {
+                expired.push(*key);
+            }
+        }
 
-        self.buffer.clear();
-        vec
+        expired.sort_unstable();
+        for (channel, note) in expired {
+            self.notes_on.remove(&(channel, note));
+            self.buffer.push(MidiMessage::Off {
+                note,
+                velocity: 0,
+                channel,
+            });
+        }
     }
 
-    pub fn all_off(&mut self, channel: u8) -> Vec<u8> {
-        let notes = self
-            .notes_on
-            .iter()
-            .flat_map(|note| {
-                MidiMessage::Off {
-                    note: *note,
-                    velocity: 0,
-                    channel,
-                }
-                .as_bytes()
-            })
-            .flatten()
-            .flatten()
-            .collect();
+    // steps until the next gate runs out
+    pub fn next_release(&self) -> Option<f32> {
+        self.notes_on.values().copied().reduce(f32::min)
+    }
+
+    // queues a message to go out with the notes of this step
+    pub fn push(&mut self, message: MidiMessage) {
+        self.buffer.push(message);
+    }
+
+    // bypasses the step buffer for messages that can't wait, like clock and transport
+    pub fn send_now(&self, message: MidiMessage) {
+        let _ = self.channel.try_send(Timed {
+            message,
+            time: Instant::now(),
+        });
+    }
+
+    pub fn tick(&mut self) -> Vec<MidiMessage> {
+        std::mem::take(&mut self.buffer)
+    }
+
+    pub fn all_off(&mut self) -> Vec<MidiMessage> {
+        let mut notes: Vec<_> = self.notes_on.drain().map(|(key, _)| key).collect();
+        notes.sort_unstable();
 
-        self.notes_on.clear();
         notes
+            .into_iter()
+            .map(|(channel, note)| MidiMessage::Off {
+                note,
+                velocity: 0,
+                channel,
+            })
+            .collect()
     }
 }
 
@@ -146,11 +235,19 @@ impl MidiLink {
 pub enum MidiError {
     #[error("value greater than 127: {message}")]
     ValueOverflow { message: MidiMessage },
+    #[error("pitch bend greater than 16383: {message}")]
+    BendOverflow { message: MidiMessage },
     #[error("channel not within (0-15): {message}")]
     ChannelOverflow { message: MidiMessage },
+    #[error("message cut short by a new status byte: {status:#04X}")]
+    Incomplete { status: u8 },
+    #[error("data byte without a status to belong to: {byte:#04X}")]
+    StrayData { byte: u8 },
+    #[error("undefined status byte: {byte:#04X}")]
+    UndefinedStatus { byte: u8 },
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum MidiMessage {
     On {
         note: u8,
@@ -167,6 +264,24 @@ pub enum MidiMessage {
         value: u8,
         channel: u8,
     },
+    // 14 bits, centered on 8192
+    PitchBend {
+        value: u16,
+        channel: u8,
+    },
+    PolyAftertouch {
+        note: u8,
+        pressure: u8,
+        channel: u8,
+    },
+    ChannelPressure {
+        pressure: u8,
+        channel: u8,
+    },
+    ProgramChange {
+        program: u8,
+        channel: u8,
+    },
     #[default]
     TimingTick,
     StartSong,
@@ -192,6 +307,20 @@ impl Display for MidiMessage {
                 value,
                 channel,
             } => format!("control change\n\tchannel: {channel}\n\tcontroller: {controller}\n\tvalue: {value}"),
+            MidiMessage::PitchBend { value, channel } => {
+                format!("pitch bend\n\tchannel: {channel}\n\tvalue: {value}")
+            }
+            MidiMessage::PolyAftertouch {
+                note,
+                pressure,
+                channel,
+            } => format!("poly aftertouch\n\tchannel: {channel}\n\tpitch: {note}\n\tpressure: {pressure}"),
+            MidiMessage::ChannelPressure { pressure, channel } => {
+                format!("channel pressure\n\tchannel: {channel}\n\tpressure: {pressure}")
+            }
+            MidiMessage::ProgramChange { program, channel } => {
+                format!("program change\n\tchannel: {channel}\n\tprogram: {program}")
+            }
             MidiMessage::TimingTick => String::from("timing tick"),
             MidiMessage::StartSong => String::from("start song"),
             MidiMessage::ContinueSong => String::from("continue song"),
@@ -202,10 +331,165 @@ impl Display for MidiMessage {
     }
 }
 
+// what the data bytes that follow belong to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Pending {
+    // a channel message under the running status
+    #[default]
+    Channel,
+    // a system exclusive dump, skipped up to its end byte
+    SysEx,
+    // the data of a system common message, skipped
+    Common(usize),
+}
+
+// turns a stream of incoming bytes back into messages, keeping the running status
+#[derive(Debug, Default)]
+pub struct MidiParser {
+    status: Option<u8>,
+    data: Vec<u8>,
+    pending: Pending,
+}
+
+impl MidiParser {
+    // errors leave the parser ready for the next message, so a stream can keep going
+    pub fn parse(&mut self, byte: u8) -> Result<Option<MidiMessage>, MidiError> {
+        match byte {
+            // realtime bytes can arrive anywhere, even between the bytes of a message
+            0xF9 | 0xFD => Err(MidiError::UndefinedStatus { byte }),
+            0xF8..=0xFF => Ok(MidiMessage::from_realtime(byte)),
+            0x80..=0xEF => self.start(Some(byte), Pending::Channel),
+            0xF0 => self.start(None, Pending::SysEx),
+            0xF7 if self.pending == Pending::SysEx => {
+                self.pending = Pending::Channel;
+                Ok(None)
+            }
+            0xF7 => {
+                self.start(None, Pending::Channel)?;
+                Err(MidiError::StrayData { byte })
+            }
+            0xF1 | 0xF3 => self.start(None, Pending::Common(1)),
+            0xF2 => self.start(None, Pending::Common(2)),
+            0xF6 => self.start(None, Pending::Channel),
+            0xF4 | 0xF5 => {
+                self.start(None, Pending::Channel)?;
+                Err(MidiError::UndefinedStatus { byte })
+            }
+            _ => self.data(byte),
+        }
+    }
+
+    // any status byte but realtime ends the message before it, and system
+    // messages cancel the running status
+    fn start(
+        &mut self,
+        status: Option<u8>,
+        pending: Pending,
+    ) -> Result<Option<MidiMessage>, MidiError> {
+        let cut = match (self.status, self.data.is_empty()) {
+            (Some(status), false) => Some(status),
+            _ => None,
+        };
+
+        self.status = status;
+        self.data.clear();
+        self.pending = pending;
+
+        match cut {
+            Some(status) => Err(MidiError::Incomplete { status }),
+            None => Ok(None),
+        }
+    }
+
+    fn data(&mut self, byte: u8) -> Result<Option<MidiMessage>, MidiError> {
+        match self.pending {
+            Pending::SysEx => return Ok(None),
+            Pending::Common(left) => {
+                self.pending = match left {
+                    1 => Pending::Channel,
+                    _ => Pending::Common(left - 1),
+                };
+                return Ok(None);
+            }
+            Pending::Channel => {}
+        }
+
+        let Some(status) = self.status else {
+            return Err(MidiError::StrayData { byte });
+        };
+
+        self.data.push(byte);
+        let len = match status & 0xF0 {
+            0xC0 | 0xD0 => 1,
+            _ => 2,
+        };
+        if self.data.len() < len {
+            return Ok(None);
+        }
+
+        let data = std::mem::take(&mut self.data);
+        let channel = status & 0x0F;
+        Ok(Some(match status & 0xF0 {
+            0x80 => MidiMessage::Off {
+                note: data[0],
+                velocity: data[1],
+                channel,
+            },
+            0x90 => MidiMessage::On {
+                note: data[0],
+                velocity: data[1],
+                channel,
+            },
+            0xA0 => MidiMessage::PolyAftertouch {
+                note: data[0],
+                pressure: data[1],
+                channel,
+            },
+            0xB0 => MidiMessage::Cc {
+                controller: data[0],
+                value: data[1],
+                channel,
+            },
+            0xC0 => MidiMessage::ProgramChange {
+                program: data[0],
+                channel,
+            },
+            0xD0 => MidiMessage::ChannelPressure {
+                pressure: data[0],
+                channel,
+            },
+            _ => MidiMessage::PitchBend {
+                value: data[0] as u16 | (data[1] as u16) << 7,
+                channel,
+            },
+        }))
+    }
+}
+
 static DATA_MASK: u8 = 0b0111_1111;
 static STATUS_MASK: u8 = 0b1111_1111;
 
 impl MidiMessage {
+    pub fn from_realtime(byte: u8) -> Option<MidiMessage> {
+        match byte {
+            0xF8 => Some(MidiMessage::TimingTick),
+            0xFA => Some(MidiMessage::StartSong),
+            0xFB => Some(MidiMessage::ContinueSong),
+            0xFC => Some(MidiMessage::StopSong),
+            _ => None,
+        }
+    }
+
+    pub fn is_realtime(&self) -> bool {
+        matches!(
+            self,
+            MidiMessage::TimingTick
+                | MidiMessage::StartSong
+                | MidiMessage::ContinueSong
+                | MidiMessage::StopSong
+        )
+    }
+
     pub fn as_bytes(&self) -> Result<[Option<u8>; 3], MidiError> {
         let mut bytes = [None; 3];
         match self {
@@ -247,10 +531,53 @@ impl MidiMessage {
                 } else if *channel > 15 {
                     return Err(MidiError::ChannelOverflow { message: *self });
                 }
-                bytes[0] = Some(STATUS_MASK & (0xD0 + channel));
+                bytes[0] = Some(STATUS_MASK & (0xB0 + channel));
                 bytes[1] = Some(DATA_MASK & controller);
                 bytes[2] = Some(DATA_MASK & value);
             }
+            MidiMessage::PitchBend { value, channel } => {
+                if *value > 0x3FFF {
+                    return Err(MidiError::BendOverflow { message: *self });
+                } else if *channel > 15 {
+                    return Err(MidiError::ChannelOverflow { message: *self });
+                }
+                // least significant seven bits first
+                bytes[0] = Some(STATUS_MASK & (0xE0 + channel));
+                bytes[1] = Some(DATA_MASK & *value as u8);
+                bytes[2] = Some(DATA_MASK & (*value >> 7) as u8);
+            }
+            MidiMessage::PolyAftertouch {
+                note,
+                pressure,
+                channel,
+            } => {
+                if *note > 127 || *pressure > 127 {
+                    return Err(MidiError::ValueOverflow { message: *self });
+                } else if *channel > 15 {
+                    return Err(MidiError::ChannelOverflow { message: *self });
+                }
+                bytes[0] = Some(STATUS_MASK & (0xA0 + channel));
+                bytes[1] = Some(DATA_MASK & note);
+                bytes[2] = Some(DATA_MASK & pressure);
+            }
+            MidiMessage::ChannelPressure { pressure, channel } => {
+                if *pressure > 127 {
+                    return Err(MidiError::ValueOverflow { message: *self });
+                } else if *channel > 15 {
+                    return Err(MidiError::ChannelOverflow { message: *self });
+                }
+                bytes[0] = Some(STATUS_MASK & (0xD0 + channel));
+                bytes[1] = Some(DATA_MASK & pressure);
+            }
+            MidiMessage::ProgramChange { program, channel } => {
+                if *program > 127 {
+                    return Err(MidiError::ValueOverflow { message: *self });
+                } else if *channel > 15 {
+                    return Err(MidiError::ChannelOverflow { message: *self });
+                }
+                bytes[0] = Some(STATUS_MASK & (0xC0 + channel));
+                bytes[1] = Some(DATA_MASK & program);
+            }
             MidiMessage::TimingTick => bytes[0] = Some(0xF8),
             MidiMessage::StartSong => bytes[0] = Some(0xFA),
             MidiMessage::ContinueSong => bytes[0] = Some(0xFB),
@@ -259,3 +586,275 @@ impl MidiMessage {
         Ok(bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+
+    fn message() -> impl Strategy<Value = MidiMessage> {
+        let channel = 0..16u8;
+        prop_oneof![
+            (0..128u8, 0..128u8, channel.clone()).prop_map(|(note, velocity, channel)| {
+                MidiMessage::On {
+                    note,
+                    velocity,
+                    channel,
+                }
+            }),
+            (0..128u8, 0..128u8, channel.clone()).prop_map(|(note, velocity, channel)| {
+                MidiMessage::Off {
+                    note,
+                    velocity,
+                    channel,
+                }
+            }),
+            (0..128u8, 0..128u8, channel.clone()).prop_map(|(controller, value, channel)| {
+                MidiMessage::Cc {
+                    controller,
+                    value,
+                    channel,
+                }
+            }),
+            (0..0x4000u16, channel.clone())
+                .prop_map(|(value, channel)| MidiMessage::PitchBend { value, channel }),
+            (0..128u8, 0..128u8, channel.clone()).prop_map(|(note, pressure, channel)| {
+                MidiMessage::PolyAftertouch {
+                    note,
+                    pressure,
+                    channel,
+                }
+            }),
+            (0..128u8, channel.clone())
+                .prop_map(|(pressure, channel)| MidiMessage::ChannelPressure { pressure, channel }),
+            (0..128u8, channel)
+                .prop_map(|(program, channel)| MidiMessage::ProgramChange { program, channel }),
+            Just(MidiMessage::TimingTick),
+            Just(MidiMessage::StartSong),
+            Just(MidiMessage::ContinueSong),
+            Just(MidiMessage::StopSong),
+        ]
+    }
+
+    fn encode(message: &MidiMessage) -> Vec<u8> {
+        message.as_bytes().unwrap().into_iter().flatten().collect()
+    }
+
+    // the same message moved to another channel, if it has one
+    fn with_channel(message: MidiMessage, channel: u8) -> Option<MidiMessage> {
+        use MidiMessage::*;
+        match message {
+            On { note, velocity, .. } => Some(On {
+                note,
+                velocity,
+                channel,
+            }),
+            Off { note, velocity, .. } => Some(Off {
+                note,
+                velocity,
+                channel,
+            }),
+            Cc {
+                controller, value, ..
+            } => Some(Cc {
+                controller,
+                value,
+                channel,
+            }),
+            PitchBend { value, .. } => Some(PitchBend { value, channel }),
+            PolyAftertouch { note, pressure, .. } => Some(PolyAftertouch {
+                note,
+                pressure,
+                channel,
+            }),
+            ChannelPressure { pressure, .. } => Some(ChannelPressure { pressure, channel }),
+            ProgramChange { program, .. } => Some(ProgramChange { program, channel }),
+            _ => None,
+        }
+    }
+
+    fn parse_all(parser: &mut MidiParser, bytes: &[u8]) -> Vec<MidiMessage> {
+        bytes
+            .iter()
+            .filter_map(|byte| parser.parse(*byte).unwrap())
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn round_trips(messages in vec(message(), 0..32)) {
+            let bytes: Vec<u8> = messages.iter().flat_map(encode).collect();
+            prop_assert_eq!(parse_all(&mut MidiParser::default(), &bytes), messages);
+        }
+
+        #[test]
+        fn round_trips_with_running_status(messages in vec(message(), 0..32)) {
+            let mut status = None;
+            let mut bytes = Vec::new();
+            for message in messages.iter() {
+                let encoded = encode(message);
+                if encoded[0] < 0xF0 && status == Some(encoded[0]) {
+                    bytes.extend(&encoded[1..]);
+                } else {
+                    // only channel messages carry a running status, and realtime keeps it
+                    if encoded[0] < 0xF8 {
+                        status = (encoded[0] < 0xF0).then_some(encoded[0]);
+                    }
+                    bytes.extend(encoded);
+                }
+            }
+
+            prop_assert_eq!(parse_all(&mut MidiParser::default(), &bytes), messages);
+        }
+
+        #[test]
+        fn realtime_passes_through(
+            messages in vec(message(), 1..16),
+            ticks in vec(any::<prop::sample::Index>(), 0..16),
+        ) {
+            let mut bytes: Vec<u8> = messages.iter().flat_map(encode).collect();
+            for tick in ticks.iter() {
+                bytes.insert(tick.index(bytes.len() + 1), 0xF8);
+            }
+
+            let parsed = parse_all(&mut MidiParser::default(), &bytes);
+            let (clock, rest): (Vec<MidiMessage>, Vec<MidiMessage>) = parsed
+                .into_iter()
+                .partition(|message| *message == MidiMessage::TimingTick);
+            let expected: Vec<MidiMessage> = messages
+                .iter()
+                .copied()
+                .filter(|message| *message != MidiMessage::TimingTick)
+                .collect();
+            let ticks_sent = messages.len() - expected.len() + ticks.len();
+
+            prop_assert_eq!(rest, expected);
+            prop_assert_eq!(clock.len(), ticks_sent);
+        }
+
+        #[test]
+        fn skips_sysex(
+            before in message(),
+            dump in vec(0..128u8, 0..64),
+            after in message(),
+        ) {
+            let mut bytes = encode(&before);
+            bytes.push(0xF0);
+            bytes.extend(dump);
+            bytes.push(0xF7);
+            bytes.extend(encode(&after));
+
+            prop_assert_eq!(parse_all(&mut MidiParser::default(), &bytes), vec![before, after]);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn encodes_in_range(message in message()) {
+            let bytes = encode(&message);
+            prop_assert!(bytes[0] >= 0x80);
+            prop_assert!(bytes[1..].iter().all(|byte| *byte < 0x80));
+        }
+
+        #[test]
+        fn rejects_data_out_of_range(data in 128..=255u8, valid in 0..128u8, channel in 0..16u8) {
+            use MidiMessage::*;
+            let overflowing = [
+                On { note: data, velocity: valid, channel },
+                On { note: valid, velocity: data, channel },
+                Off { note: data, velocity: valid, channel },
+                Off { note: valid, velocity: data, channel },
+                Cc { controller: data, value: valid, channel },
+                Cc { controller: valid, value: data, channel },
+                PolyAftertouch { note: data, pressure: valid, channel },
+                PolyAftertouch { note: valid, pressure: data, channel },
+                ChannelPressure { pressure: data, channel },
+                ProgramChange { program: data, channel },
+            ];
+
+            for message in overflowing {
+                prop_assert!(
+                    matches!(message.as_bytes(), Err(MidiError::ValueOverflow { .. })),
+                    "{}", message
+                );
+            }
+        }
+
+        #[test]
+        fn rejects_bends_out_of_range(value in 0x4000..=u16::MAX, channel in 0..16u8) {
+            let message = MidiMessage::PitchBend { value, channel };
+            prop_assert!(
+                matches!(message.as_bytes(), Err(MidiError::BendOverflow { .. })),
+                "{}", message
+            );
+        }
+
+        #[test]
+        fn rejects_channels_out_of_range(message in message(), channel in 16..=255u8) {
+            let Some(message) = with_channel(message, channel) else {
+                return Ok(());
+            };
+            prop_assert!(
+                matches!(message.as_bytes(), Err(MidiError::ChannelOverflow { .. })),
+                "{}", message
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_stray_data() {
+        let mut parser = MidiParser::default();
+        assert!(matches!(
+            parser.parse(0x40),
+            Err(MidiError::StrayData { byte: 0x40 })
+        ));
+    }
+
+    #[test]
+    fn rejects_incomplete_messages() {
+        let mut parser = MidiParser::default();
+        assert_eq!(parser.parse(0x90).unwrap(), None);
+        assert_eq!(parser.parse(0x3C).unwrap(), None);
+        assert!(matches!(
+            parser.parse(0x80),
+            Err(MidiError::Incomplete { status: 0x90 })
+        ));
+
+        // the new status still holds
+        assert_eq!(parser.parse(0x3C).unwrap(), None);
+        assert_eq!(
+            parser.parse(0x00).unwrap(),
+            Some(MidiMessage::Off {
+                note: 0x3C,
+                velocity: 0,
+                channel: 0
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_undefined_status() {
+        let mut parser = MidiParser::default();
+        for byte in [0xF4, 0xF5, 0xF9, 0xFD] {
+            assert!(matches!(
+                parser.parse(byte),
+                Err(MidiError::UndefinedStatus { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn system_common_cancels_running_status() {
+        let mut parser = MidiParser::default();
+        let note = parse_all(&mut parser, &[0x90, 0x3C, 0x40]);
+        assert_eq!(note.len(), 1);
+
+        // song position pointer, with its two data bytes skipped
+        assert!(parse_all(&mut parser, &[0xF2, 0x10, 0x20]).is_empty());
+        assert!(matches!(
+            parser.parse(0x3C),
+            Err(MidiError::StrayData { byte: 0x3C })
+        ));
+    }
+}
@@ -1,10 +1,12 @@
 use std::fmt::Display;
 
-use rand::random;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::MidiInfo;
+use crate::{Cell, GridInfo, MidiInfo};
 
-#[derive(Clone, Copy, Eq, PartialEq, Default, Debug)]
+#[derive(Clone, Copy, Eq, PartialEq, Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Scale {
     #[default]
     Chromatic,
@@ -20,9 +22,44 @@ pub enum Scale {
     MelodicMinor,
     HarmonicMinor,
     WholeTone,
+    // whatever is held on the keyboard at the midi input
+    HeldNotes,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Eq, PartialEq, Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PitchMode {
+    #[default]
+    Random,
+    Row,
+    Column,
+    Drums,
+}
+
+impl PitchMode {
+    pub const ALL: [PitchMode; 4] = [
+        PitchMode::Random,
+        PitchMode::Row,
+        PitchMode::Column,
+        PitchMode::Drums,
+    ];
+}
+
+impl Display for PitchMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            PitchMode::Random => "random pitch",
+            PitchMode::Row => "pitch by row",
+            PitchMode::Column => "pitch by column",
+            PitchMode::Drums => "drum lanes",
+        };
+
+        write!(f, "{str}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Octave {
     pub center: u8,
     pub range: u8,
@@ -37,7 +74,7 @@ impl Default for Octave {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct Velocity {
     min: u8,
     max: u8,
@@ -56,6 +93,10 @@ impl Velocity {
         self.max = max;
     }
 
+    pub fn clamped(self) -> Self {
+        Self::new(self.min.min(127), self.max.min(127))
+    }
+
     pub fn min(&self) -> u8 {
         self.min
     }
@@ -65,7 +106,83 @@ impl Velocity {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+// how long a note is held, in steps, picked anew between min and max for every note
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Gate {
+    min: f32,
+    max: f32,
+}
+
+impl Default for Gate {
+    fn default() -> Self {
+        Self { min: 1.0, max: 1.0 }
+    }
+}
+
+impl Gate {
+    pub const MIN: f32 = 0.125;
+    pub const MAX: f32 = 16.0;
+
+    pub fn new(min: f32, max: f32) -> Self {
+        Self { min, max }
+    }
+
+    pub fn set_min(&mut self, min: f32) {
+        self.min = min;
+    }
+
+    pub fn set_max(&mut self, max: f32) {
+        self.max = max;
+    }
+
+    pub fn min(&self) -> f32 {
+        self.min
+    }
+
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+}
+
+// fixed notes for the rows of a drum layer, counted from the bottom and repeating
+// up the grid once the lanes run out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Drums {
+    notes: [u8; Drums::LANES],
+}
+
+impl Default for Drums {
+    // general midi kick, snare, hats and toms, then percussion and cymbals
+    fn default() -> Self {
+        Self {
+            notes: [
+                36, 38, 42, 46, 39, 37, 41, 45, 48, 50, 44, 54, 56, 70, 49, 51,
+            ],
+        }
+    }
+}
+
+impl Drums {
+    pub const LANES: usize = 16;
+    // general midi puts percussion on channel 10
+    pub const CHANNEL: u8 = 9;
+
+    pub fn note(&self, lane: usize) -> u8 {
+        self.notes[lane % Self::LANES]
+    }
+
+    pub fn set_note(&mut self, lane: usize, note: u8) {
+        self.notes[lane % Self::LANES] = note.min(127);
+    }
+
+    pub fn clamped(self) -> Self {
+        Self {
+            notes: self.notes.map(|note| note.min(127)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct Root {
     pub note: RootNote,
     pub accidental: Accidental,
@@ -77,6 +194,28 @@ impl Display for Root {
     }
 }
 
+impl Root {
+    // the root with the pitch class of a midi note, spelled with sharps
+    pub fn from_note(note: u8) -> Self {
+        let (note, accidental) = match note % 12 {
+            0 => (RootNote::C, Accidental::Natural),
+            1 => (RootNote::C, Accidental::Sharp),
+            2 => (RootNote::D, Accidental::Natural),
+            3 => (RootNote::D, Accidental::Sharp),
+            4 => (RootNote::E, Accidental::Natural),
+            5 => (RootNote::F, Accidental::Natural),
+            6 => (RootNote::F, Accidental::Sharp),
+            7 => (RootNote::G, Accidental::Natural),
+            8 => (RootNote::G, Accidental::Sharp),
+            9 => (RootNote::A, Accidental::Natural),
+            10 => (RootNote::A, Accidental::Sharp),
+            _ => (RootNote::B, Accidental::Natural),
+        };
+
+        Self { note, accidental }
+    }
+}
+
 impl From<Root> for u8 {
     fn from(val: Root) -> Self {
         let n = match val.note {
@@ -97,7 +236,8 @@ impl From<Root> for u8 {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RootNote {
     A,
     B,
@@ -137,7 +277,8 @@ impl RootNote {
     ];
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Accidental {
     #[default]
     Natural,
@@ -200,12 +341,14 @@ impl From<Scale> for [bool; 12] {
             Scale::WholeTone => [
                 true, false, true, false, true, false, true, false, true, false, true, false,
             ],
+            // held notes aren't tied to the root, so they are picked from the keyboard instead
+            Scale::HeldNotes => [true; 12],
         }
     }
 }
 
 impl Scale {
-    pub const ALL: [Scale; 13] = [
+    pub const ALL: [Scale; 14] = [
         Scale::Chromatic,
         Scale::Major,
         Scale::Minor,
@@ -219,6 +362,7 @@ impl Scale {
         Scale::MelodicMinor,
         Scale::HarmonicMinor,
         Scale::WholeTone,
+        Scale::HeldNotes,
     ];
 }
 
@@ -238,22 +382,39 @@ impl Display for Scale {
             Scale::MelodicMinor => "melodic minor",
             Scale::HarmonicMinor => "harmonic minor",
             Scale::WholeTone => "whole tone",
+            Scale::HeldNotes => "held notes",
         };
 
         write!(f, "{str}")
     }
 }
 
-pub fn generate_note(info: &MidiInfo) -> u8 {
+// nothing is played while the held notes scale has no keys held
+pub fn generate_note(info: &MidiInfo, held: &[u8], rng: &mut impl Rng) -> Option<u8> {
     let root: u8 = info.root.into();
 
     let oct_mod = if info.octave.range != 0 {
-        random::<u8>() % info.octave.range
+        rng.gen::<u8>() % info.octave.range
     } else {
         0
     };
 
-    let octave = if random::<bool>() {
+    // held notes keep the octave they were played in, spread out by the range
+    if info.scale == Scale::HeldNotes {
+        if held.is_empty() {
+            return None;
+        }
+
+        let note = held[rng.gen::<usize>() % held.len()] as isize;
+        let shift = oct_mod as isize * 12;
+        return Some(fold(if rng.gen::<bool>() {
+            note + shift
+        } else {
+            note - shift
+        }));
+    }
+
+    let octave = if rng.gen::<bool>() {
         info.octave.center.saturating_add(oct_mod)
     } else {
         info.octave.center.saturating_sub(oct_mod)
@@ -262,21 +423,237 @@ pub fn generate_note(info: &MidiInfo) -> u8 {
     let scale: [bool; 12] = info.scale.into();
 
     let degree = loop {
-        let r = random::<usize>() % 12;
+        let r = rng.gen::<usize>() % 12;
         if scale[r] {
-            break r.try_into().unwrap();
+            break r;
         } else {
             continue;
         }
     };
 
-    octave
-        .saturating_mul(12)
-        .saturating_add(root)
-        .saturating_add(degree)
+    Some(fold(octave as isize * 12 + root as isize + degree as isize))
+}
+
+// moves a note by whole octaves until it fits in the midi range, so it stays in
+// the scale even when the octaves asked for go past either end
+fn fold(note: isize) -> u8 {
+    let mut note = note;
+    while note > 127 {
+        note -= 12;
+    }
+    while note < 0 {
+        note += 12;
+    }
+    note as u8
+}
+
+// walks up the scale one degree per row (bottom to top) or column (left to right),
+// wrapping around the octave range once the scale runs out
+pub fn map_note(
+    info: &MidiInfo,
+    cell: Cell,
+    grid: GridInfo,
+    held: &[u8],
+    rng: &mut impl Rng,
+) -> Option<u8> {
+    let step = match info.pitch_mode {
+        PitchMode::Random => return generate_note(info, held, rng),
+        PitchMode::Drums => {
+            let lane = (grid.height as isize - 1 - cell.i).max(0);
+            return Some(info.drums.note(lane as usize));
+        }
+        PitchMode::Row => grid.height as isize - 1 - cell.i,
+        PitchMode::Column => cell.j,
+    };
+
+    let range = info.octave.range as isize;
+    let octaves = |len: isize| step.div_euclid(len).rem_euclid(2 * range + 1) - range;
+
+    // held notes are walked from lowest to highest, shifting whole octaves as the step grows
+    if info.scale == Scale::HeldNotes {
+        let mut notes: Vec<isize> = held.iter().map(|note| *note as isize).collect();
+        if notes.is_empty() {
+            return None;
+        }
+        notes.sort_unstable();
+
+        let len = notes.len() as isize;
+        let note = notes[step.rem_euclid(len) as usize] + octaves(len) * 12;
+        return Some(fold(note));
+    }
+
+    let scale: [bool; 12] = info.scale.into();
+    let degrees: Vec<isize> = (0..12).filter(|d| scale[*d as usize]).collect();
+    let len = degrees.len() as isize;
+
+    let octave = info.octave.center as isize + octaves(len);
+
+    let root: u8 = info.root.into();
+    let note = octave * 12 + root as isize + degrees[step.rem_euclid(len) as usize];
+
+    Some(fold(note))
 }
 
-pub fn generate_velocity(v: Velocity) -> u8 {
-    let range = v.max - v.min;
-    v.min + (random::<u8>() % range)
+// a range set the wrong way round is read the right way round, and a range of
+// one velocity always gives that one
+pub fn generate_velocity(v: Velocity, rng: &mut impl Rng) -> u8 {
+    let (min, max) = (v.min.min(v.max), v.min.max(v.max));
+    rng.gen_range(min..=max)
+}
+
+pub fn generate_gate(g: Gate, rng: &mut impl Rng) -> f32 {
+    let (min, max) = (g.min.min(g.max), g.min.max(g.max));
+    (min + rng.gen::<f32>() * (max - min)).max(Gate::MIN)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*, sample::select};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn root() -> impl Strategy<Value = Root> {
+        (
+            select(RootNote::ALL.to_vec()),
+            select(Accidental::ALL.to_vec()),
+        )
+            .prop_map(|(note, accidental)| Root { note, accidental })
+    }
+
+    // the steps in semitones between the degrees of a scale, going once round the octave
+    fn intervals(scale: Scale) -> Vec<usize> {
+        let table: [bool; 12] = scale.into();
+        let degrees: Vec<usize> = (0..12).filter(|degree| table[*degree]).collect();
+        degrees
+            .iter()
+            .zip(degrees.iter().skip(1).chain([&12]))
+            .map(|(degree, next)| next - degree)
+            .collect()
+    }
+
+    #[test]
+    fn scale_tables() {
+        let expected: [(Scale, &[usize]); 14] = [
+            (Scale::Chromatic, &[1; 12]),
+            (Scale::Major, &[2, 2, 1, 2, 2, 2, 1]),
+            (Scale::Minor, &[2, 1, 2, 2, 1, 2, 2]),
+            (Scale::Dorian, &[2, 1, 2, 2, 2, 1, 2]),
+            (Scale::Phrygian, &[1, 2, 2, 2, 1, 2, 2]),
+            (Scale::Lydian, &[2, 2, 2, 1, 2, 2, 1]),
+            (Scale::Mixolydian, &[2, 2, 1, 2, 2, 1, 2]),
+            (Scale::Locrian, &[1, 2, 2, 1, 2, 2, 2]),
+            (Scale::MinorPentatonic, &[3, 2, 2, 3, 2]),
+            (Scale::MajorPentatonic, &[2, 2, 3, 2, 3]),
+            (Scale::MelodicMinor, &[2, 1, 2, 2, 2, 2, 1]),
+            (Scale::HarmonicMinor, &[2, 1, 2, 2, 1, 3, 1]),
+            (Scale::WholeTone, &[2; 6]),
+            (Scale::HeldNotes, &[1; 12]),
+        ];
+
+        for (scale, steps) in expected {
+            assert_eq!(intervals(scale), steps, "{scale}");
+        }
+    }
+
+    #[test]
+    fn held_notes_need_keys() {
+        let info = MidiInfo {
+            scale: Scale::HeldNotes,
+            ..MidiInfo::default()
+        };
+        assert_eq!(
+            generate_note(&info, &[], &mut StdRng::seed_from_u64(0)),
+            None
+        );
+    }
+
+    #[test]
+    fn rows_past_either_end_stay_in_scale() {
+        let grid = GridInfo {
+            width: 1,
+            height: 64,
+            ..GridInfo::default()
+        };
+        let table: [bool; 12] = Scale::Major.into();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for center in [0, 10] {
+            let info = MidiInfo {
+                scale: Scale::Major,
+                pitch_mode: PitchMode::Row,
+                octave: Octave { center, range: 4 },
+                ..MidiInfo::default()
+            };
+            let notes: Vec<u8> = [0, 1, 62, 63]
+                .into_iter()
+                .map(|i| map_note(&info, Cell::new(i, 0), grid, &[], &mut rng).unwrap())
+                .collect();
+
+            for note in notes.iter() {
+                assert!(*note <= 127);
+                assert!(table[*note as usize % 12], "{note} is not in the scale");
+            }
+            // neighbouring rows keep different pitches instead of piling up on one
+            assert_ne!(notes[0], notes[1]);
+            assert_ne!(notes[2], notes[3]);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn notes_stay_in_range_and_scale(
+            scale in select(Scale::ALL[..].iter().copied().filter(|scale| {
+                *scale != Scale::HeldNotes
+            }).collect::<Vec<_>>()),
+            root in root(),
+            center in 0..=10u8,
+            range in 0..=10u8,
+            seed in any::<u64>(),
+        ) {
+            let info = MidiInfo {
+                scale,
+                root,
+                octave: Octave { center, range },
+                ..MidiInfo::default()
+            };
+            let note = generate_note(&info, &[], &mut StdRng::seed_from_u64(seed)).unwrap();
+
+            let table: [bool; 12] = scale.into();
+            let root: u8 = root.into();
+            prop_assert!(note <= 127);
+            prop_assert!(table[(note as isize - root as isize).rem_euclid(12) as usize]);
+        }
+
+        #[test]
+        fn held_notes_keep_their_pitch(
+            held in vec(0..128u8, 1..8),
+            range in 0..=10u8,
+            seed in any::<u64>(),
+        ) {
+            let info = MidiInfo {
+                scale: Scale::HeldNotes,
+                octave: Octave { center: 4, range },
+                ..MidiInfo::default()
+            };
+            let note = generate_note(&info, &held, &mut StdRng::seed_from_u64(seed)).unwrap();
+
+            prop_assert!(note <= 127);
+            prop_assert!(held.iter().any(|key| key % 12 == note % 12));
+        }
+
+        #[test]
+        fn velocities_stay_in_range(min in 0..128u8, max in 0..128u8, seed in any::<u64>()) {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let velocity = generate_velocity(Velocity::new(min, max), &mut rng);
+            prop_assert!((min.min(max)..=min.max(max)).contains(&velocity));
+        }
+
+        #[test]
+        fn gates_stay_in_range(min in 0.0..16.0f32, max in 0.0..16.0f32, seed in any::<u64>()) {
+            let gate = generate_gate(Gate::new(min, max), &mut StdRng::seed_from_u64(seed));
+            prop_assert!(gate >= Gate::MIN);
+            prop_assert!(gate <= min.max(max).max(Gate::MIN));
+        }
+    }
 }
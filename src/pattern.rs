@@ -0,0 +1,311 @@
+use std::{fs, path::Path};
+
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::{rule::Rule, Cell, CellMap};
+
+#[derive(Debug, Error)]
+pub enum PatternError {
+    #[error("could not access pattern file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("unknown pattern format (expected .rle or .cells): {0}")]
+    Format(String),
+    #[error("unexpected character in pattern: {0}")]
+    Character(char),
+    #[error("run of more than {max} cells in pattern", max = MAX_SIZE)]
+    Run,
+    #[error("pattern of {0}x{1} cells is larger than {max}x{max}", max = MAX_SIZE)]
+    Size(usize, usize),
+}
+
+// the most cells a pattern can span either way, far past any grid, so that a
+// broken file or a map that has spread out doesn't take all the memory
+pub const MAX_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternFormat {
+    Rle,
+    Plaintext,
+}
+
+impl PatternFormat {
+    pub fn from_path(path: &Path) -> Result<Self, PatternError> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("rle") => Ok(PatternFormat::Rle),
+            Some("cells") => Ok(PatternFormat::Plaintext),
+            _ => Err(PatternError::Format(path.display().to_string())),
+        }
+    }
+}
+
+pub fn import(path: &Path, offset: Cell) -> Result<CellMap, PatternError> {
+    let format = PatternFormat::from_path(path)?;
+    let text = fs::read_to_string(path)?;
+
+    match format {
+        PatternFormat::Rle => parse_rle(&text, offset),
+        PatternFormat::Plaintext => parse_plaintext(&text, offset),
+    }
+}
+
+pub fn export(path: &Path, cells: &CellMap, rule: Option<Rule>) -> Result<(), PatternError> {
+    let text = match PatternFormat::from_path(path)? {
+        PatternFormat::Rle => write_rle(cells, rule)?,
+        PatternFormat::Plaintext => write_plaintext(cells)?,
+    };
+
+    fs::write(path, text)?;
+    Ok(())
+}
+
+pub fn parse_rle(text: &str, offset: Cell) -> Result<CellMap, PatternError> {
+    let mut cells = CellMap::default();
+    let mut run: Option<isize> = None;
+    let (mut i, mut j) = (0, 0);
+
+    let body = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#') && !line.starts_with('x'));
+
+    'parse: for line in body {
+        for c in line.chars() {
+            let count = run.unwrap_or(1);
+
+            match c {
+                '0'..='9' => {
+                    let digit = c.to_digit(10).unwrap_or_default() as isize;
+                    run = Some(
+                        run.unwrap_or(0)
+                            .checked_mul(10)
+                            .and_then(|run| run.checked_add(digit))
+                            .filter(|run| *run <= MAX_SIZE as isize)
+                            .ok_or(PatternError::Run)?,
+                    );
+                    continue;
+                }
+                'b' | '.' => j += count,
+                'o' => {
+                    for n in 0..count {
+                        cells.insert(Cell {
+                            i: i + offset.i,
+                            j: j + n + offset.j,
+                        });
+                    }
+                    j += count;
+                }
+                '$' => {
+                    i += count;
+                    j = 0;
+                }
+                '!' => break 'parse,
+                c if c.is_whitespace() => {}
+                c => return Err(PatternError::Character(c)),
+            }
+
+            run = None;
+        }
+    }
+
+    Ok(cells)
+}
+
+pub fn parse_plaintext(text: &str, offset: Cell) -> Result<CellMap, PatternError> {
+    let mut cells = CellMap::default();
+
+    let rows = text
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.starts_with('!'));
+
+    for (i, row) in rows.enumerate() {
+        for (j, c) in row.chars().enumerate() {
+            match c {
+                'O' | 'o' | '*' => {
+                    cells.insert(Cell {
+                        i: i as isize + offset.i,
+                        j: j as isize + offset.j,
+                    });
+                }
+                '.' => {}
+                c => return Err(PatternError::Character(c)),
+            }
+        }
+    }
+
+    Ok(cells)
+}
+
+// the smallest rectangle holding every cell, as its top left corner and size
+fn bounds(cells: &CellMap) -> (Cell, usize, usize) {
+    if cells.is_empty() {
+        return (Cell::default(), 0, 0);
+    }
+
+    let (top, bottom) = cells
+        .iter()
+        .map(|cell| cell.i)
+        .minmax()
+        .into_option()
+        .unwrap_or_default();
+    let (left, right) = cells
+        .iter()
+        .map(|cell| cell.j)
+        .minmax()
+        .into_option()
+        .unwrap_or_default();
+
+    (
+        Cell { i: top, j: left },
+        (right - left + 1) as usize,
+        (bottom - top + 1) as usize,
+    )
+}
+
+fn rows(cells: &CellMap) -> Result<(usize, Vec<Vec<bool>>), PatternError> {
+    let (corner, width, height) = bounds(cells);
+    if width > MAX_SIZE || height > MAX_SIZE {
+        return Err(PatternError::Size(width, height));
+    }
+
+    let rows = (0..height as isize)
+        .map(|i| {
+            (0..width as isize)
+                .map(|j| {
+                    cells.contains(&Cell {
+                        i: corner.i + i,
+                        j: corner.j + j,
+                    })
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok((width, rows))
+}
+
+pub fn write_rle(cells: &CellMap, rule: Option<Rule>) -> Result<String, PatternError> {
+    let (width, rows) = rows(cells)?;
+
+    let mut header = format!("x = {}, y = {}", width, rows.len());
+    if let Some(rule) = rule {
+        header.push_str(&format!(", rule = {rule}"));
+    }
+
+    let mut runs: Vec<(usize, char)> = Vec::new();
+    let mut push = |count: usize, tag: char| match runs.last_mut() {
+        Some((n, last)) if *last == tag => *n += count,
+        _ => runs.push((count, tag)),
+    };
+
+    for (n, row) in rows.iter().enumerate() {
+        if n > 0 {
+            push(1, '$');
+        }
+
+        // trailing dead cells in a row are implied
+        let end = row.iter().rposition(|alive| *alive).map_or(0, |p| p + 1);
+        for (alive, run) in &row[..end].iter().group_by(|alive| **alive) {
+            push(run.count(), if alive { 'o' } else { 'b' });
+        }
+    }
+
+    let mut body = String::new();
+    let mut line_len = 0;
+    for token in runs
+        .into_iter()
+        .map(|(count, tag)| match count {
+            1 => tag.to_string(),
+            n => format!("{n}{tag}"),
+        })
+        .chain(std::iter::once(String::from("!")))
+    {
+        // rle lines are kept under 70 characters
+        if line_len + token.len() > 70 {
+            body.push('\n');
+            line_len = 0;
+        }
+        line_len += token.len();
+        body.push_str(&token);
+    }
+
+    Ok(format!("{header}\n{body}\n"))
+}
+
+pub fn write_plaintext(cells: &CellMap) -> Result<String, PatternError> {
+    let (_, rows) = rows(cells)?;
+
+    let mut text = String::from("!Name: cellseq\n");
+    for row in rows {
+        let line: String = row
+            .iter()
+            .map(|alive| if *alive { 'O' } else { '.' })
+            .collect();
+        text.push_str(line.trim_end_matches('.'));
+        text.push('\n');
+    }
+
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glider() -> CellMap {
+        [(0, 1), (1, 2), (2, 0), (2, 1), (2, 2)]
+            .into_iter()
+            .map(|(i, j)| Cell::new(i, j))
+            .collect()
+    }
+
+    #[test]
+    fn rle_round_trip() {
+        let text = write_rle(&glider(), Some(Rule::default())).unwrap();
+        assert_eq!(
+            text,
+            format!("x = 3, y = 3, rule = {}\nbo$2bo$3o!\n", Rule::default())
+        );
+        assert_eq!(parse_rle(&text, Cell::default()).unwrap(), glider());
+    }
+
+    #[test]
+    fn plaintext_round_trip() {
+        let text = write_plaintext(&glider()).unwrap();
+        assert_eq!(text, "!Name: cellseq\n.O\n..O\nOOO\n");
+        assert_eq!(parse_plaintext(&text, Cell::default()).unwrap(), glider());
+    }
+
+    #[test]
+    fn imports_at_an_offset() {
+        let path = std::env::temp_dir().join(format!("cellseq-glider-{}.rle", std::process::id()));
+        fs::write(&path, "#N Glider\nx = 3, y = 3\nbo$2bo$3o!\n").unwrap();
+        let cells = import(&path, Cell::new(5, -3));
+        fs::remove_file(&path).unwrap();
+
+        let moved: CellMap = glider()
+            .into_iter()
+            .map(|cell| Cell::new(cell.i + 5, cell.j - 3))
+            .collect();
+        assert_eq!(cells.unwrap(), moved);
+    }
+
+    #[test]
+    fn long_runs_are_refused() {
+        let text = "x = 1, y = 1\n99999999999999999999999o!\n";
+        assert!(matches!(
+            parse_rle(text, Cell::default()),
+            Err(PatternError::Run)
+        ));
+    }
+
+    #[test]
+    fn spread_out_maps_are_refused() {
+        let cells = CellMap::from_iter([Cell::new(0, 0), Cell::new(0, MAX_SIZE as isize)]);
+        assert!(matches!(
+            write_rle(&cells, None),
+            Err(PatternError::Size(width, 1)) if width == MAX_SIZE + 1
+        ));
+    }
+}
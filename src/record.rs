@@ -0,0 +1,211 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{MidiError, MidiMessage, SongInfo};
+
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("could not write recording: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Midi(#[from] MidiError),
+}
+
+// the messages coming out of the sequencer, placed by how many steps went by
+// between them, to be written out as a standard midi file
+#[derive(Debug, Clone)]
+pub struct Recording {
+    path: PathBuf,
+    // quarter notes since the recording started
+    position: f64,
+    bpm: usize,
+    tempos: Vec<(u32, usize)>,
+    events: Vec<(u32, MidiMessage)>,
+    // notes that are still on, to be let go at the end
+    sounding: HashSet<(u8, u8)>,
+}
+
+impl Recording {
+    // ticks per quarter note
+    pub const DIVISION: u16 = 480;
+
+    pub fn new(path: PathBuf, song: &SongInfo) -> Self {
+        Self {
+            path,
+            position: 0.0,
+            bpm: song.bpm,
+            tempos: vec![(0, song.bpm)],
+            events: Vec::new(),
+            sounding: HashSet::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn tick(&self) -> u32 {
+        (self.position * Self::DIVISION as f64).round() as u32
+    }
+
+    // moves along by a (possibly fractional) number of steps at the current
+    // note division, noting any change of tempo on the way
+    pub fn advance(&mut self, steps: f32, song: &SongInfo) {
+        if song.bpm != self.bpm {
+            self.bpm = song.bpm;
+            self.tempos.push((self.tick(), song.bpm));
+        }
+        self.position += steps as f64 * 4.0 / song.divisor.max(1) as f64;
+    }
+
+    pub fn record(&mut self, messages: &[MidiMessage]) {
+        let tick = self.tick();
+        for message in messages {
+            match *message {
+                MidiMessage::On {
+                    note,
+                    velocity,
+                    channel,
+                } if velocity > 0 => {
+                    self.sounding.insert((channel, note));
+                }
+                MidiMessage::On { note, channel, .. } | MidiMessage::Off { note, channel, .. } => {
+                    self.sounding.remove(&(channel, note));
+                }
+                _ => {}
+            }
+            self.events.push((tick, *message));
+        }
+    }
+
+    pub fn save(&self) -> Result<(), RecordingError> {
+        fs::write(&self.path, self.to_smf()?)?;
+        Ok(())
+    }
+
+    // a type 1 file: the tempo map on the first track and then a track for every
+    // midi channel played on
+    pub fn to_smf(&self) -> Result<Vec<u8>, MidiError> {
+        let end = self.tick();
+        let mut events = self.events.clone();
+        let mut sounding: Vec<_> = self.sounding.iter().copied().collect();
+        sounding.sort_unstable();
+        events.extend(sounding.into_iter().map(|(channel, note)| {
+            (
+                end,
+                MidiMessage::Off {
+                    note,
+                    velocity: 0,
+                    channel,
+                },
+            )
+        }));
+
+        let mut channels: [Vec<(u32, Vec<u8>)>; 16] = Default::default();
+        for (tick, message) in events {
+            let bytes: Vec<u8> = message.as_bytes()?.into_iter().flatten().collect();
+            // clock and transport have nowhere to go in a file
+            if bytes[0] < 0xF0 {
+                channels[(bytes[0] & 0x0F) as usize].push((tick, bytes));
+            }
+        }
+
+        let tempos = self
+            .tempos
+            .iter()
+            .map(|(tick, bpm)| {
+                // microseconds per quarter note, in the 24 bits there are for it,
+                // so anything slower than 4 bpm is written as the slowest it can be
+                let tempo = (60_000_000 / (*bpm).max(1) as u32)
+                    .min(0xFF_FFFF)
+                    .to_be_bytes();
+                (*tick, vec![0xFF, 0x51, 0x03, tempo[1], tempo[2], tempo[3]])
+            })
+            .collect();
+
+        let mut tracks = vec![tempos];
+        tracks.extend(channels.into_iter().filter(|track| !track.is_empty()));
+
+        let mut smf = Vec::new();
+        smf.extend(b"MThd");
+        smf.extend(6u32.to_be_bytes());
+        smf.extend(1u16.to_be_bytes());
+        smf.extend((tracks.len() as u16).to_be_bytes());
+        smf.extend(Self::DIVISION.to_be_bytes());
+        for track in tracks {
+            let chunk = track_chunk(track, end);
+            smf.extend(b"MTrk");
+            smf.extend((chunk.len() as u32).to_be_bytes());
+            smf.extend(chunk);
+        }
+
+        Ok(smf)
+    }
+}
+
+// events in order with the time since the one before, closed by an end of track
+fn track_chunk(events: Vec<(u32, Vec<u8>)>, end: u32) -> Vec<u8> {
+    let mut chunk = Vec::new();
+    let mut last = 0;
+    for (tick, bytes) in events {
+        variable_length(&mut chunk, tick - last);
+        chunk.extend(bytes);
+        last = tick;
+    }
+    variable_length(&mut chunk, end.saturating_sub(last));
+    chunk.extend([0xFF, 0x2F, 0x00]);
+    chunk
+}
+
+// seven bits to a byte, most significant first, with the top bit set on all but
+// the last
+fn variable_length(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.into_iter().rev());
+}
+
+// the first of name-1.mid, name-2.mid and so on that isn't taken yet, next to
+// the session file
+pub fn take_path(session: &Path) -> PathBuf {
+    let stem = session
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("cellseq");
+
+    (1..)
+        .map(|n| session.with_file_name(format!("{stem}-{n}.mid")))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the tempo of the first event on the tempo track
+    fn tempo(bpm: usize) -> Vec<u8> {
+        let song = SongInfo {
+            bpm,
+            ..SongInfo::default()
+        };
+        let smf = Recording::new(PathBuf::new(), &song).to_smf().unwrap();
+        smf[23..29].to_vec()
+    }
+
+    #[test]
+    fn tempos_fit_in_24_bits() {
+        assert_eq!(tempo(120), [0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20]);
+        assert_eq!(tempo(4), [0xFF, 0x51, 0x03, 0xE4, 0xE1, 0xC0]);
+        assert_eq!(tempo(1), [0xFF, 0x51, 0x03, 0xFF, 0xFF, 0xFF]);
+    }
+}
@@ -0,0 +1,174 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rule {
+    birth: [bool; 9],
+    survival: [bool; 9],
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        RulePreset::Life.into()
+    }
+}
+
+impl Rule {
+    pub fn born(&self, neighbors: usize) -> bool {
+        self.birth.get(neighbors).copied().unwrap_or(false)
+    }
+
+    pub fn survives(&self, neighbors: usize) -> bool {
+        self.survival.get(neighbors).copied().unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RuleError {
+    #[error("rule not in B/S notation: {0}")]
+    Notation(String),
+    #[error("neighbor count not within (0-8): {0}")]
+    Count(char),
+    #[error("birth with zero neighbors is not supported")]
+    BirthOnZero,
+}
+
+fn parse_counts(counts: &str) -> Result<[bool; 9], RuleError> {
+    let mut set = [false; 9];
+    for c in counts.chars() {
+        match c.to_digit(10) {
+            Some(n) if n < 9 => set[n as usize] = true,
+            _ => return Err(RuleError::Count(c)),
+        }
+    }
+    Ok(set)
+}
+
+impl FromStr for Rule {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let notation = || RuleError::Notation(s.to_string());
+
+        let (birth, survival) = s.trim().split_once('/').ok_or_else(notation)?;
+
+        let birth = birth
+            .strip_prefix(['B', 'b'])
+            .ok_or_else(notation)
+            .and_then(parse_counts)?;
+
+        let survival = survival
+            .strip_prefix(['S', 's'])
+            .ok_or_else(notation)
+            .and_then(parse_counts)?;
+
+        if birth[0] {
+            return Err(RuleError::BirthOnZero);
+        }
+
+        Ok(Self { birth, survival })
+    }
+}
+
+impl TryFrom<String> for Rule {
+    type Error = RuleError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Rule> for String {
+    fn from(rule: Rule) -> Self {
+        rule.to_string()
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let counts = |set: &[bool; 9]| -> String {
+            (0..9).filter(|n| set[*n]).map(|n| n.to_string()).collect()
+        };
+
+        write!(f, "B{}/S{}", counts(&self.birth), counts(&self.survival))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RulePreset {
+    #[default]
+    Life,
+    HighLife,
+    Seeds,
+    DayAndNight,
+    Maze,
+    LifeWithoutDeath,
+    Morley,
+    TwoByTwo,
+    Diamoeba,
+    Replicator,
+}
+
+impl RulePreset {
+    pub const ALL: [RulePreset; 10] = [
+        RulePreset::Life,
+        RulePreset::HighLife,
+        RulePreset::Seeds,
+        RulePreset::DayAndNight,
+        RulePreset::Maze,
+        RulePreset::LifeWithoutDeath,
+        RulePreset::Morley,
+        RulePreset::TwoByTwo,
+        RulePreset::Diamoeba,
+        RulePreset::Replicator,
+    ];
+
+    pub fn notation(&self) -> &'static str {
+        match self {
+            RulePreset::Life => "B3/S23",
+            RulePreset::HighLife => "B36/S23",
+            RulePreset::Seeds => "B2/S",
+            RulePreset::DayAndNight => "B3678/S34678",
+            RulePreset::Maze => "B3/S12345",
+            RulePreset::LifeWithoutDeath => "B3/S012345678",
+            RulePreset::Morley => "B368/S245",
+            RulePreset::TwoByTwo => "B36/S125",
+            RulePreset::Diamoeba => "B35678/S5678",
+            RulePreset::Replicator => "B1357/S1357",
+        }
+    }
+
+    pub fn matching(rule: Rule) -> Option<RulePreset> {
+        RulePreset::ALL
+            .into_iter()
+            .find(|preset| Rule::from(*preset) == rule)
+    }
+}
+
+impl From<RulePreset> for Rule {
+    fn from(preset: RulePreset) -> Self {
+        preset.notation().parse().expect("preset rules are valid")
+    }
+}
+
+impl Display for RulePreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            RulePreset::Life => "life",
+            RulePreset::HighLife => "highlife",
+            RulePreset::Seeds => "seeds",
+            RulePreset::DayAndNight => "day & night",
+            RulePreset::Maze => "maze",
+            RulePreset::LifeWithoutDeath => "life without death",
+            RulePreset::Morley => "morley",
+            RulePreset::TwoByTwo => "2x2",
+            RulePreset::Diamoeba => "diamoeba",
+            RulePreset::Replicator => "replicator",
+        };
+
+        write!(f, "{str}")
+    }
+}
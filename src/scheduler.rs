@@ -0,0 +1,342 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc as feed;
+
+use crate::{MidiMessage, Sequencer, Snapshot, SongInfo, Timed};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    TogglePlayback,
+    // timing settings changed, recalculate the upcoming deadlines
+    Update,
+    Clock(MidiMessage),
+    Quit,
+}
+
+// handle to the thread driving the sequencer, stopping it when dropped
+#[derive(Debug)]
+pub struct Scheduler {
+    events: Sender<Event>,
+}
+
+impl Scheduler {
+    pub fn spawn(sequencer: Arc<Mutex<Sequencer>>) -> (Scheduler, Snapshots) {
+        let (events, event_rcv) = mpsc::channel();
+        let (snapshot_snd, snapshot_rcv) = feed::channel(8);
+
+        let worker = Worker {
+            sequencer,
+            events: event_rcv,
+            snapshots: snapshot_snd,
+            steps: Ticker::new(Duration::ZERO),
+            pulses: Ticker::new(Duration::ZERO),
+            gates: Instant::now(),
+        };
+
+        thread::spawn(move || worker.run());
+
+        (Scheduler { events }, Snapshots::new(snapshot_rcv))
+    }
+
+    pub fn sender(&self) -> Sender<Event> {
+        self.events.clone()
+    }
+
+    pub fn send(&self, event: Event) {
+        let _ = self.events.send(event);
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.send(Event::Quit);
+    }
+}
+
+pub type Snapshots = Feed<Snapshot>;
+
+// shared so the gui can keep listening across subscription rebuilds
+#[derive(Debug)]
+pub struct Feed<T> {
+    channel: Arc<tokio::sync::Mutex<feed::Receiver<T>>>,
+}
+
+impl<T> Clone for Feed<T> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Feed<T> {
+    pub fn new(channel: feed::Receiver<T>) -> Self {
+        Self {
+            channel: Arc::new(tokio::sync::Mutex::new(channel)),
+        }
+    }
+
+    pub async fn recv(&self) -> Option<T> {
+        self.channel.lock().await.recv().await
+    }
+}
+
+// evenly spaced deadlines measured from a fixed anchor, so rounding never accumulates
+#[derive(Debug)]
+struct Ticker {
+    anchor: Instant,
+    period: Duration,
+    count: u32,
+}
+
+impl Ticker {
+    fn new(period: Duration) -> Self {
+        Self {
+            anchor: Instant::now(),
+            period,
+            count: 0,
+        }
+    }
+
+    fn deadline(&self) -> Instant {
+        self.anchor + self.period * self.count
+    }
+
+    fn advance(&mut self) {
+        self.count += 1;
+    }
+
+    fn retime(&mut self, period: Duration) {
+        if period != self.period {
+            self.anchor = self.deadline();
+            self.count = 0;
+            self.period = period;
+        }
+    }
+}
+
+fn step_period(song: &SongInfo) -> Duration {
+    Duration::from_secs_f64(240.0 / song.bpm.saturating_mul(song.divisor).max(1) as f64)
+}
+
+// midi clock pulses in a whole note
+const WHOLE_NOTE_PULSES: usize = 96;
+
+// midi clock runs at 24 pulses per quarter note
+fn pulse_period(song: &SongInfo) -> Duration {
+    Duration::from_secs_f64(60.0 / song.bpm.saturating_mul(24).max(1) as f64)
+}
+
+fn send(channel: &feed::Sender<Timed>, messages: Vec<MidiMessage>, time: Instant) {
+    for message in messages {
+        if channel.blocking_send(Timed { message, time }).is_err() {
+            break;
+        }
+    }
+}
+
+struct Worker {
+    sequencer: Arc<Mutex<Sequencer>>,
+    events: Receiver<Event>,
+    snapshots: feed::Sender<Snapshot>,
+    steps: Ticker,
+    pulses: Ticker,
+    // when the note gates were last counted down
+    gates: Instant,
+}
+
+impl Worker {
+    fn run(mut self) {
+        loop {
+            let event = match self.deadline() {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    match self.events.recv_timeout(timeout) {
+                        Ok(event) => Some(event),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                None => match self.events.recv() {
+                    Ok(event) => Some(event),
+                    Err(_) => return,
+                },
+            };
+
+            match event {
+                Some(Event::Quit) => return,
+                Some(Event::TogglePlayback) => self.toggle(),
+                Some(Event::Update) => {}
+                Some(Event::Clock(message)) => self.external(message),
+                None => self.fire(),
+            }
+        }
+    }
+
+    // the next time something is due on the internal clock, if it is running
+    fn deadline(&mut self) -> Option<Instant> {
+        let song = self.sequencer.lock().unwrap().song;
+        if !song.is_playing || song.external_sync {
+            return None;
+        }
+
+        self.steps.retime(step_period(&song));
+        self.pulses.retime(pulse_period(&song));
+
+        let step = match self.release() {
+            Some(release) => self.steps.deadline().min(release),
+            None => self.steps.deadline(),
+        };
+
+        if song.clock_out {
+            Some(step.min(self.pulses.deadline()))
+        } else {
+            // keep the clock in phase with the steps for when it is turned back on
+            self.pulses = Ticker {
+                anchor: self.steps.deadline(),
+                period: pulse_period(&song),
+                count: 0,
+            };
+            Some(step)
+        }
+    }
+
+    // when the next note gate runs out, measured in steps from the last count down
+    fn release(&self) -> Option<Instant> {
+        let steps = self.sequencer.lock().unwrap().midi.next_release()?;
+        Some(self.gates + self.steps.period.mul_f32(steps.max(0.0)))
+    }
+
+    fn release_until(&mut self, until: Instant) {
+        let period = self.steps.period.as_secs_f32();
+        if period > 0.0 {
+            let elapsed = until.saturating_duration_since(self.gates).as_secs_f32();
+            self.count_down(elapsed / period, until);
+        }
+        self.gates = until;
+    }
+
+    fn count_down(&self, steps: f32, time: Instant) {
+        let mut sequencer = self.sequencer.lock().unwrap();
+        let messages = sequencer.release(steps);
+        let channel = sequencer.midi.channel_handle();
+        drop(sequencer);
+
+        send(&channel, messages, time);
+    }
+
+    fn fire(&mut self) {
+        let now = Instant::now();
+
+        if self.pulses.deadline() <= now {
+            let sequencer = self.sequencer.lock().unwrap();
+            if sequencer.song.clock_out {
+                sequencer.midi.send_now(MidiMessage::TimingTick);
+            }
+            drop(sequencer);
+            self.pulses.advance();
+        }
+
+        if let Some(release) = self.release().filter(|release| *release <= now) {
+            self.release_until(release);
+        }
+
+        if self.steps.deadline() <= now {
+            // notes ending on this step are let go before the new ones start
+            self.release_until(self.steps.deadline());
+            self.step(self.steps.deadline());
+            self.steps.advance();
+        }
+    }
+
+    fn step(&self, time: Instant) {
+        let mut sequencer = self.sequencer.lock().unwrap();
+        let messages = sequencer.step();
+        let snapshot = sequencer.snapshot();
+        let channel = sequencer.midi.channel_handle();
+        drop(sequencer);
+
+        send(&channel, messages, time);
+
+        let _ = self.snapshots.try_send(snapshot);
+    }
+
+    fn toggle(&mut self) {
+        let mut sequencer = self.sequencer.lock().unwrap();
+        let song = sequencer.song;
+
+        if song.is_playing {
+            for message in sequencer.midi.all_off() {
+                sequencer.midi.send_now(message);
+            }
+        } else {
+            self.steps = Ticker::new(step_period(&song));
+            self.pulses = Ticker::new(pulse_period(&song));
+            self.gates = self.steps.anchor;
+            // the first pulse after a start is the downbeat
+            sequencer.song.clock_phase = WHOLE_NOTE_PULSES;
+        }
+
+        sequencer.song.is_playing = !song.is_playing;
+
+        // a take ends when playback stops
+        let recording = if song.is_playing {
+            sequencer.stop_recording()
+        } else {
+            None
+        };
+
+        if song.clock_out && !song.external_sync {
+            sequencer.midi.send_now(if song.is_playing {
+                MidiMessage::StopSong
+            } else {
+                MidiMessage::StartSong
+            });
+        }
+
+        let _ = self.snapshots.try_send(sequencer.snapshot());
+        drop(sequencer);
+
+        // written once the sequencer is let go, so a slow disk holds nothing up
+        if let Some(recording) = recording {
+            if let Err(e) = recording.save() {
+                eprintln!("{e}");
+            }
+        }
+    }
+
+    fn external(&mut self, message: MidiMessage) {
+        let song = self.sequencer.lock().unwrap().song;
+        if !song.external_sync {
+            return;
+        }
+
+        match message {
+            MidiMessage::StartSong | MidiMessage::ContinueSong if !song.is_playing => self.toggle(),
+            MidiMessage::StopSong if song.is_playing => self.toggle(),
+            MidiMessage::TimingTick if song.is_playing => {
+                // a step lasts 96 / divisor pulses, which needn't be a whole number
+                let divisor = song.divisor.max(1);
+                self.count_down(divisor as f32 / WHOLE_NOTE_PULSES as f32, Instant::now());
+
+                let mut sequencer = self.sequencer.lock().unwrap();
+                let due = sequencer.song.clock_phase / WHOLE_NOTE_PULSES;
+                sequencer.song.clock_phase =
+                    sequencer.song.clock_phase % WHOLE_NOTE_PULSES + divisor;
+                drop(sequencer);
+
+                for _ in 0..due {
+                    self.step(Instant::now());
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use std::path::PathBuf;
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    map::{self, Map},
+    mask::Mask,
+    Binding, CcOutput, CellMap, Control, GridInfo, Keyboard, KeyboardInfo, MidiInfo, MidiLink,
+    MidiMessage, Recording, SavedLayer, Session, SongInfo, Stats,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub map: CellMap,
+    pub mask: CellMap,
+    pub hits: CellMap,
+    pub grid: GridInfo,
+}
+
+// one mask over the shared map, played with its own midi settings
+#[derive(Debug, Default)]
+pub struct Layer {
+    pub(crate) mask: Mask,
+    pub(crate) info: MidiInfo,
+}
+
+impl Layer {
+    pub fn info(&self) -> &MidiInfo {
+        &self.info
+    }
+
+    pub fn info_mut(&mut self) -> &mut MidiInfo {
+        &mut self.info
+    }
+
+    pub fn mask(&self) -> &Mask {
+        &self.mask
+    }
+
+    pub fn mask_mut(&mut self) -> &mut Mask {
+        &mut self.mask
+    }
+}
+
+// the whole sequencing engine, without a window or a clock: every call to step
+// advances the map and returns the midi it produced, and release counts the note
+// gates down in between. the scheduler and the window both drive one of these
+#[derive(Debug)]
+pub struct Sequencer {
+    pub(crate) map: Map,
+    pub(crate) layers: Vec<Layer>,
+    // the layer shown on the mask board and edited by the controls
+    pub(crate) layer: usize,
+    pub(crate) midi: MidiLink,
+    pub(crate) song: SongInfo,
+    pub(crate) grid: GridInfo,
+    pub(crate) keyboard: Keyboard,
+    pub(crate) keys: KeyboardInfo,
+    pub(crate) bindings: Vec<Binding>,
+    pub(crate) cc_outputs: Vec<CcOutput>,
+    pub(crate) recording: Option<Recording>,
+    // every random choice comes from here, so the same seed plays the same way
+    random_seed: u64,
+    rng: StdRng,
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        let seed = rand::random();
+        Self {
+            map: Map::default(),
+            layers: vec![Layer::default()],
+            layer: 0,
+            midi: MidiLink::default(),
+            song: SongInfo::default(),
+            grid: GridInfo::default(),
+            keyboard: Keyboard::default(),
+            keys: KeyboardInfo::default(),
+            bindings: Vec::new(),
+            cc_outputs: Vec::new(),
+            recording: None,
+            random_seed: seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sequencer {
+    // one layer per midi channel at most
+    pub const MAX_LAYERS: usize = 16;
+
+    pub fn new(midi: MidiLink) -> Self {
+        Self {
+            midi,
+            ..Self::default()
+        }
+    }
+
+    // advances the map by one step and returns the midi messages it produced
+    pub fn step(&mut self) -> Vec<MidiMessage> {
+        let map = if self.song.is_looping && self.song.step_num >= self.song.loop_len {
+            self.song.step_num = 1;
+            self.map.reset_loop()
+        } else {
+            self.song.step_num += 1;
+            self.map.tick()
+        };
+
+        let before = self.map.cells().clone();
+        self.map.update(map::Message::Ticked(map.clone()));
+
+        let mut hit_count = 0;
+        for layer in self.layers.iter_mut() {
+            let hits = layer.mask.tick(&map);
+            hit_count += hits.len();
+            self.midi.update(
+                &hits,
+                &layer.info,
+                self.grid,
+                self.keyboard.held(),
+                &mut self.rng,
+            );
+        }
+
+        let stats = Stats::measure(&before, &map, hit_count, self.grid);
+        for output in self.cc_outputs.iter_mut() {
+            let value = stats.value(output.metric);
+            if output.last != Some(value) {
+                output.last = Some(value);
+                self.midi.push(MidiMessage::Cc {
+                    controller: output.controller,
+                    value,
+                    channel: output.channel,
+                });
+            }
+        }
+
+        self.flush()
+    }
+
+    // counts the note gates down between steps, returning the note offs
+    pub fn release(&mut self, steps: f32) -> Vec<MidiMessage> {
+        self.midi.release(steps);
+        if let Some(recording) = self.recording.as_mut() {
+            recording.advance(steps, &self.song);
+        }
+
+        self.flush()
+    }
+
+    // plays a number of steps straight through without waiting on a clock, with
+    // every note off placed where its gate runs out, and lets the last notes ring
+    // out to the end
+    pub fn render(&mut self, steps: usize) {
+        for step in 0..steps {
+            if step > 0 {
+                let mut left = 1.0;
+                while let Some(next) = self.midi.next_release().filter(|next| *next < left) {
+                    let next = next.max(0.0);
+                    self.release(next);
+                    left -= next;
+                }
+                self.release(left);
+            }
+            self.step();
+        }
+
+        while let Some(next) = self.midi.next_release() {
+            self.release(next.max(0.0));
+        }
+    }
+
+    // a take starts with the program of every layer, so the patches come back
+    pub fn start_recording(&mut self, path: PathBuf) {
+        let mut recording = Recording::new(path, &self.song);
+        let programs: Vec<_> = self
+            .layers
+            .iter()
+            .filter_map(|layer| {
+                layer
+                    .info
+                    .program
+                    .map(|program| MidiMessage::ProgramChange {
+                        program,
+                        channel: layer.info.channel,
+                    })
+            })
+            .collect();
+        recording.record(&programs);
+        self.recording = Some(recording);
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    fn flush(&mut self) -> Vec<MidiMessage> {
+        let messages = self.midi.tick();
+        if let Some(recording) = self.recording.as_mut() {
+            recording.record(&messages);
+        }
+        messages
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let layer = self.layer();
+        Snapshot {
+            map: self.map.cells().clone(),
+            mask: layer.mask.cells().clone(),
+            hits: layer.mask.hits().clone(),
+            grid: self.grid,
+        }
+    }
+
+    pub fn song(&self) -> SongInfo {
+        self.song
+    }
+
+    pub fn song_mut(&mut self) -> &mut SongInfo {
+        &mut self.song
+    }
+
+    pub fn grid(&self) -> GridInfo {
+        self.grid
+    }
+
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    pub fn map_mut(&mut self) -> &mut Map {
+        &mut self.map
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn cc_outputs_mut(&mut self) -> &mut Vec<CcOutput> {
+        &mut self.cc_outputs
+    }
+
+    pub fn layer(&self) -> &Layer {
+        &self.layers[self.layer]
+    }
+
+    pub fn layer_mut(&mut self) -> &mut Layer {
+        &mut self.layers[self.layer]
+    }
+
+    pub fn select_layer(&mut self, layer: usize) {
+        self.layer = layer.min(self.layers.len() - 1);
+    }
+
+    // new layers start empty on the next channel up
+    pub fn add_layer(&mut self) {
+        if self.layers.len() >= Self::MAX_LAYERS {
+            return;
+        }
+
+        let mut mask = Mask::default();
+        mask.set_grid(self.grid);
+        mask.set_randomness(self.map.randomness());
+
+        self.layers.push(Layer {
+            mask,
+            info: MidiInfo {
+                channel: self.layers.len() as u8,
+                ..MidiInfo::default()
+            },
+        });
+        self.layer = self.layers.len() - 1;
+    }
+
+    // notes still sounding on a removed layer end with their gates
+    pub fn remove_layer(&mut self) {
+        if self.layers.len() > 1 {
+            self.layers.remove(self.layer);
+            self.select_layer(self.layer);
+        }
+    }
+
+    // a controller drives one control, and a control follows one controller
+    pub fn bind(&mut self, binding: Binding) {
+        self.bindings.retain(|bound| {
+            bound.control != binding.control
+                && (bound.channel, bound.controller) != (binding.channel, binding.controller)
+        });
+        self.bindings.push(binding);
+    }
+
+    pub fn bound(&self, channel: u8, controller: u8) -> Option<Control> {
+        self.bindings
+            .iter()
+            .find(|bound| bound.channel == channel && bound.controller == controller)
+            .map(|bound| bound.control)
+    }
+
+    pub fn random_seed(&self) -> u64 {
+        self.random_seed
+    }
+
+    // starts the random choices over from the seed
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random_seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn randomize_map(&mut self) {
+        self.map.randomize(&mut self.rng);
+    }
+
+    pub fn randomize_mask(&mut self) {
+        let layer = self.layer;
+        self.layers[layer].mask.randomize(&mut self.rng);
+    }
+
+    pub fn toggle_loop(&mut self) {
+        self.song.is_looping = !self.song.is_looping;
+        if self.song.is_looping {
+            self.map.set_loop();
+            self.song.step_num = 1;
+        }
+    }
+
+    pub fn set_grid(&mut self, grid: GridInfo) {
+        self.grid = grid;
+        self.map.set_grid(grid);
+        for layer in self.layers.iter_mut() {
+            layer.mask.set_grid(grid);
+        }
+    }
+
+    pub fn set_randomness(&mut self, value: f32) {
+        self.map.set_randomness(value);
+        for layer in self.layers.iter_mut() {
+            layer.mask.set_randomness(value);
+        }
+    }
+
+    pub fn session(&self) -> Session {
+        Session {
+            song: self.song,
+            grid: self.grid,
+            keyboard: self.keys,
+            bindings: self.bindings.clone(),
+            cc_outputs: self.cc_outputs.clone(),
+            rule: self.map.rule(),
+            topology: self.map.topology(),
+            randomness: self.map.randomness(),
+            random_seed: self.random_seed,
+            seed: self.map.seed().clone(),
+            map: self.map.cells().clone(),
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| SavedLayer {
+                    info: layer.info,
+                    mask: layer.mask.cells().clone(),
+                })
+                .collect(),
+        }
+    }
+
+    // anything out of bounds in the file is brought back within the limits the
+    // controls keep to
+    pub fn load_session(&mut self, session: Session) {
+        self.song = SongInfo {
+            is_playing: self.song.is_playing,
+            ..session.song.clamped()
+        };
+
+        self.layers = session
+            .layers
+            .into_iter()
+            .map(|saved| {
+                let mut mask = Mask::default();
+                mask.set_cells(saved.mask);
+                Layer {
+                    mask,
+                    info: saved.info.clamped(),
+                }
+            })
+            .collect();
+        if self.layers.is_empty() {
+            self.layers.push(Layer::default());
+        }
+        self.layers.truncate(Self::MAX_LAYERS);
+        self.layer = 0;
+        self.keys = session.keyboard;
+        self.bindings = session.bindings.into_iter().map(Binding::clamped).collect();
+        self.cc_outputs = session
+            .cc_outputs
+            .into_iter()
+            .map(CcOutput::clamped)
+            .collect();
+
+        self.set_grid(session.grid.clamped());
+        self.set_randomness(session.randomness);
+        self.set_random_seed(session.random_seed);
+        self.map.set_rule(session.rule);
+        self.map.set_topology(session.topology);
+        self.map.restore(session.seed, session.map);
+
+        for layer in self.layers.iter() {
+            if let Some(program) = layer.info.program {
+                self.midi.send_now(MidiMessage::ProgramChange {
+                    program,
+                    channel: layer.info.channel,
+                });
+            }
+        }
+    }
+}
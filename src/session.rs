@@ -0,0 +1,93 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::{
+    map::Topology, rule::Rule, Binding, CcOutput, CellMap, GridInfo, KeyboardInfo, MidiInfo,
+    SongInfo,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub song: SongInfo,
+    pub grid: GridInfo,
+    pub keyboard: KeyboardInfo,
+    pub bindings: Vec<Binding>,
+    pub cc_outputs: Vec<CcOutput>,
+    pub rule: Rule,
+    pub topology: Topology,
+    pub randomness: f32,
+    // where the random choices start from, not to be confused with the saved map
+    #[serde(with = "random_seed")]
+    pub random_seed: u64,
+    pub seed: CellMap,
+    pub map: CellMap,
+    pub layers: Vec<SavedLayer>,
+}
+
+// a mask layer along with the midi settings its hits are played with
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedLayer {
+    pub info: MidiInfo,
+    pub mask: CellMap,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            song: SongInfo::default(),
+            grid: GridInfo::default(),
+            keyboard: KeyboardInfo::default(),
+            bindings: Vec::new(),
+            cc_outputs: Vec::new(),
+            rule: Rule::default(),
+            topology: Topology::default(),
+            randomness: 0.5,
+            // files from before there was a seed all play back the same way
+            random_seed: 0,
+            seed: CellMap::default(),
+            map: CellMap::default(),
+            layers: vec![SavedLayer::default()],
+        }
+    }
+}
+
+// toml only has signed integers, so the top half of the seeds is written as
+// negative numbers and read back the same
+mod random_seed {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(seed: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(*seed as i64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        Ok(i64::deserialize(deserializer)? as u64)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("could not access session file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not read session: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("could not write session: {0}")]
+    Write(#[from] toml::ser::Error),
+}
+
+impl Session {
+    pub fn open(path: &Path) -> Result<Self, SessionError> {
+        let contents = fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SessionError> {
+        let contents = toml::to_string(self)?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+}
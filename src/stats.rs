@@ -0,0 +1,125 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CellMap, GridInfo};
+
+// something measured from the automaton on every step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    #[default]
+    Population,
+    Hits,
+    Births,
+    Deaths,
+    CentroidX,
+    CentroidY,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 6] = [
+        Metric::Population,
+        Metric::Hits,
+        Metric::Births,
+        Metric::Deaths,
+        Metric::CentroidX,
+        Metric::CentroidY,
+    ];
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Metric::Population => "population",
+            Metric::Hits => "hits",
+            Metric::Births => "births",
+            Metric::Deaths => "deaths",
+            Metric::CentroidX => "centroid x",
+            Metric::CentroidY => "centroid y",
+        };
+
+        write!(f, "{str}")
+    }
+}
+
+// a control change stream following one metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CcOutput {
+    pub metric: Metric,
+    pub controller: u8,
+    pub channel: u8,
+    // the last value sent, so a control change only goes out when it moves
+    #[serde(skip)]
+    pub last: Option<u8>,
+}
+
+impl Default for CcOutput {
+    fn default() -> Self {
+        Self {
+            metric: Metric::Population,
+            // general purpose controller 1
+            controller: 16,
+            channel: 0,
+            last: None,
+        }
+    }
+}
+
+impl CcOutput {
+    pub fn clamped(self) -> Self {
+        Self {
+            controller: self.controller.min(127),
+            channel: self.channel.min(15),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    population: usize,
+    hits: usize,
+    births: usize,
+    deaths: usize,
+    centroid: (f32, f32),
+    grid: GridInfo,
+}
+
+impl Stats {
+    pub fn measure(before: &CellMap, after: &CellMap, hits: usize, grid: GridInfo) -> Self {
+        let (i, j) = after.iter().fold((0.0, 0.0), |(i, j), cell| {
+            (i + cell.i as f32, j + cell.j as f32)
+        });
+        let count = after.len().max(1) as f32;
+
+        Self {
+            population: after.len(),
+            hits,
+            births: after.difference(before).count(),
+            deaths: before.difference(after).count(),
+            centroid: (i / count, j / count),
+            grid,
+        }
+    }
+
+    // the population is measured against the size of the board and the centroid
+    // across it (bottom to top for y), while the counts per step go out as they are
+    pub fn value(&self, metric: Metric) -> u8 {
+        let (width, height) = (
+            self.grid.width.max(2) as f32,
+            self.grid.height.max(2) as f32,
+        );
+        let value = match metric {
+            Metric::Population => self.population as f32 * 127.0 / (width * height),
+            Metric::Hits => self.hits as f32,
+            Metric::Births => self.births as f32,
+            Metric::Deaths => self.deaths as f32,
+            Metric::CentroidX => self.centroid.1 * 127.0 / (width - 1.0),
+            Metric::CentroidY => (height - 1.0 - self.centroid.0) * 127.0 / (height - 1.0),
+        };
+
+        value.round().clamp(0.0, 127.0) as u8
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use cellseq::*;
+use tokio::sync::mpsc::channel;
+
+const GLIDER: [(isize, isize); 5] = [(0, 1), (1, 2), (2, 0), (2, 1), (2, 2)];
+
+// a glider crossing a mask that covers the whole board, with every hit played
+fn sequencer(seed: u64) -> Sequencer {
+    let mut sequencer = Sequencer::default();
+    sequencer.set_random_seed(seed);
+
+    for (i, j) in GLIDER {
+        sequencer
+            .map_mut()
+            .update(map::Message::Populate(Cell::new(i + 4, j + 4)));
+    }
+    for i in 0..24 {
+        for j in 0..24 {
+            sequencer
+                .layer_mut()
+                .mask_mut()
+                .update(mask::Message::Check(Cell::new(i, j)));
+        }
+    }
+
+    let info = sequencer.layer_mut().info_mut();
+    info.probability = 1.0;
+    info.scale = Scale::Major;
+    info.velocity = Velocity::new(40, 100);
+    info.octave = Octave {
+        center: 9,
+        range: 3,
+    };
+
+    sequencer
+}
+
+// steps a quarter of a second apart into the in-memory sink
+fn play(sequencer: &mut Sequencer, steps: u32) -> Vec<Timed> {
+    let recorder = Recorder::default();
+    let mut sink = recorder.clone();
+    let start = Instant::now();
+
+    for step in 0..steps {
+        let time = start + Duration::from_millis(250) * step;
+        let mut messages = sequencer.release(1.0);
+        messages.extend(sequencer.step());
+        for message in messages {
+            sink.send(message, time).unwrap();
+        }
+    }
+
+    recorder.messages()
+}
+
+#[test]
+fn notes_are_struck_and_released() {
+    let messages = play(&mut sequencer(1), 32);
+    assert!(!messages.is_empty());
+
+    let mut sounding = HashSet::new();
+    for Timed { message, .. } in messages.iter() {
+        match *message {
+            MidiMessage::On {
+                note,
+                velocity,
+                channel,
+            } => {
+                assert!(sounding.insert((channel, note)), "{note} struck twice");
+                assert!((40..=100).contains(&velocity));
+                assert!(note <= 127);
+                assert!([0, 2, 4, 5, 7, 9, 11].contains(&(note % 12)));
+            }
+            MidiMessage::Off { note, channel, .. } => {
+                assert!(sounding.remove(&(channel, note)), "{note} let go twice");
+            }
+            _ => {}
+        }
+    }
+
+    assert!(messages.windows(2).all(|pair| pair[0].time <= pair[1].time));
+}
+
+#[test]
+fn same_seed_plays_the_same() {
+    let notes = |messages: Vec<Timed>| -> Vec<MidiMessage> {
+        messages.into_iter().map(|timed| timed.message).collect()
+    };
+
+    let first = notes(play(&mut sequencer(7), 16));
+    assert_eq!(first, notes(play(&mut sequencer(7), 16)));
+    assert_ne!(first, notes(play(&mut sequencer(8), 16)));
+}
+
+#[test]
+fn programs_go_out_when_a_session_opens() {
+    let mut saved = sequencer(1);
+    saved.layer_mut().info_mut().program = Some(12);
+    let session = saved.session();
+
+    let (send, mut receive) = channel(16);
+    let mut sequencer = Sequencer::new(MidiLink::new(send));
+    sequencer.load_session(session);
+
+    assert_eq!(
+        receive.try_recv().unwrap().message,
+        MidiMessage::ProgramChange {
+            program: 12,
+            channel: 0
+        }
+    );
+}
+
+#[test]
+fn renders_to_a_midi_file() {
+    let mut sequencer = sequencer(1);
+    sequencer.start_recording("unused.mid".into());
+    sequencer.render(16);
+
+    let smf = sequencer.stop_recording().unwrap().to_smf().unwrap();
+    assert_eq!(&smf[..4], b"MThd");
+    // format 1, with the tempo track and the one channel played on
+    assert_eq!(&smf[8..12], &[0, 1, 0, 2]);
+}
+
+#[test]
+fn sessions_out_of_bounds_are_brought_within_limits() {
+    let mut saved = sequencer(1);
+    saved.cc_outputs_mut().push(CcOutput {
+        controller: 200,
+        channel: 20,
+        ..CcOutput::default()
+    });
+    saved.bind(Binding {
+        channel: 20,
+        controller: 200,
+        control: Control::Bpm,
+    });
+
+    let mut session = saved.session();
+    session.grid.width = 0;
+    session.grid.height = 0;
+    session.song.bpm = i64::MAX as usize;
+    session.song.divisor = 0;
+    session.topology = Topology::Torus;
+    let info = &mut session.layers[0].info;
+    info.channel = 20;
+    info.velocity = Velocity::new(150, 200);
+    info.program = Some(200);
+    info.pitch_mode = PitchMode::Drums;
+
+    // drum notes can only be set out of range in the file itself
+    let path = std::env::temp_dir().join(format!("cellseq-limits-{}.toml", std::process::id()));
+    session.save(&path).unwrap();
+    let text = std::fs::read_to_string(&path)
+        .unwrap()
+        .replace("[36, ", "[200, ");
+    std::fs::write(&path, text).unwrap();
+    let session = Session::open(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut sequencer = Sequencer::default();
+    sequencer.load_session(session);
+    sequencer.start_recording("unused.mid".into());
+    sequencer.render(16);
+    assert!(sequencer.stop_recording().unwrap().to_smf().is_ok());
+
+    assert_eq!(sequencer.grid().width, GridInfo::MIN_CELLS);
+    assert_eq!(sequencer.grid().height, GridInfo::MIN_CELLS);
+    assert_eq!(sequencer.song().bpm, SongInfo::MAX_BPM);
+    assert_eq!(sequencer.song().divisor, 1);
+
+    let info = sequencer.layers()[0].info();
+    assert_eq!(info.channel, 15);
+    assert_eq!(info.velocity, Velocity::new(127, 127));
+    assert_eq!(info.program, Some(127));
+    assert_eq!(info.drums.note(0), 127);
+
+    let output = sequencer.cc_outputs_mut()[0];
+    assert_eq!((output.controller, output.channel), (127, 15));
+    assert_eq!(sequencer.bound(15, 127), Some(Control::Bpm));
+}
+
+#[test]
+fn seeds_survive_a_save() {
+    let path = std::env::temp_dir().join(format!("cellseq-seed-{}.toml", std::process::id()));
+
+    let mut sequencer = sequencer(1);
+    sequencer.set_random_seed(u64::MAX - 3);
+    sequencer.session().save(&path).unwrap();
+    let seed = Session::open(&path).unwrap().random_seed;
+
+    // a file from before seeds were saved
+    std::fs::write(&path, "randomness = 0.5\n").unwrap();
+    let old = [Session::open(&path).unwrap(), Session::open(&path).unwrap()];
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(seed, u64::MAX - 3);
+    assert_eq!(old[0].random_seed, old[1].random_seed);
+}